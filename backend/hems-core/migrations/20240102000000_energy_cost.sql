@@ -0,0 +1,2 @@
+-- Cost of each interval's grid import, priced from the tariffs table
+ALTER TABLE energy_data ADD COLUMN cost REAL NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS ev_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    arrival DATETIME NOT NULL,
+    departure DATETIME NOT NULL,
+    required_kwh REAL NOT NULL,
+    max_rate_kw REAL NOT NULL,
+    delivered_kwh REAL NOT NULL DEFAULT 0
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS appliance_runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    program TEXT NOT NULL,
+    earliest_start DATETIME NOT NULL,
+    finish_by DATETIME NOT NULL,
+    start_time DATETIME NOT NULL,
+    end_time DATETIME NOT NULL
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS device_readings (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    timestamp DATETIME NOT NULL,
+    power REAL NOT NULL,
+    energy REAL NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_readings_device_time ON device_readings(device_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_device_readings_time ON device_readings(timestamp);
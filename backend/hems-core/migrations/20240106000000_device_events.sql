@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS device_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp DATETIME NOT NULL,
+    device_id INTEGER NOT NULL,
+    device_name TEXT NOT NULL,
+    old_state BOOLEAN NOT NULL,
+    new_state BOOLEAN NOT NULL,
+    actor TEXT NOT NULL,
+    reason TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_events_time ON device_events(timestamp);
+-- Latest event per device, looked up on every simulation step
+CREATE INDEX IF NOT EXISTS idx_device_events_device ON device_events(device_id, id);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS device_overrides (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    is_on BOOLEAN NOT NULL,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_overrides_expiry ON device_overrides(expires_at);
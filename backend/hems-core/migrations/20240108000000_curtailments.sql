@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS curtailments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp DATETIME NOT NULL,
+    device_id INTEGER NOT NULL,
+    device_name TEXT NOT NULL,
+    requested_power REAL NOT NULL,
+    allowed_power REAL NOT NULL,
+    reason TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_curtailments_time ON curtailments(timestamp);
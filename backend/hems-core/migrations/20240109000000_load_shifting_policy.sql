@@ -0,0 +1,5 @@
+-- Load-shifting policy as JSON in a single row, so it survives restarts
+CREATE TABLE IF NOT EXISTS load_shifting_policy (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    policy TEXT NOT NULL
+);
@@ -1,70 +1,246 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fs;
-use std::path::Path;
-use rand::Rng;
+use std::path::PathBuf;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use sqlx::SqlitePool;
 use crate::models::Device;
+use crate::tariff::TariffSchedule;
+use crate::battery::Battery;
+use crate::ev::{self, ChargeSlot, EvDemand, EV_CHARGER};
+use crate::thermal::{HvacMode, ThermalState, HVAC};
+use crate::water_heater::{self, HeatingStrategy, WaterTank, WATER_HEATER};
+use crate::load_shifting::{LoadShiftingPolicy, RestoreStrategy, MAX_RESTORE_DELAY_MINUTES};
+use crate::load_management::{Load, LoadManagement};
+use crate::solar::SolarModel;
+use std::sync::Arc;
+use chrono::{Datelike, Months, NaiveDate, Weekday};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 pub enum Scenario {
     Baseline,
     Solar,
     SmartShift,
+    SolarBattery,
+    SolarBatterySmartShift,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 5] = [
+        Scenario::Baseline,
+        Scenario::Solar,
+        Scenario::SmartShift,
+        Scenario::SolarBattery,
+        Scenario::SolarBatterySmartShift,
+    ];
+
+    fn has_solar(self) -> bool {
+        !matches!(self, Scenario::Baseline)
+    }
+
+    fn has_battery(self) -> bool {
+        matches!(self, Scenario::SolarBattery | Scenario::SolarBatterySmartShift)
+    }
+
+    fn shifts_load(self) -> bool {
+        matches!(self, Scenario::SmartShift | Scenario::SolarBatterySmartShift)
+    }
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct AnalysisRecord {
+    pub date: NaiveDate,
     pub time_step: String,
     pub scenario: String,
     pub solar_generation: f64,
     pub home_consumption: f64,
     pub grid_import: f64,
     pub grid_export: f64,
+    pub battery_charge: f64,
+    pub battery_discharge: f64,
+    pub battery_soc: f64, // 0 when the scenario has no battery
     pub cost: f64,
     pub is_peak: bool,
 }
 
-pub async fn run_analysis(pool: &SqlitePool) -> Result<(Vec<String>, String, Vec<AnalysisRecord>), Box<dyn Error>> {
-    let scenarios = vec![Scenario::Baseline, Scenario::Solar, Scenario::SmartShift];
+/// Everything a simulated day depends on besides the scenario.
+pub struct DayInputs<'a> {
+    pub devices: &'a [Device],
+    pub solar_profile: &'a [f64],
+    pub usage: &'a [f64], // Share of each appliance's rating in use per step
+    pub tariff: &'a TariffSchedule,
+    pub battery: &'a Battery,
+    pub ev_demands: &'a [EvDemand],
+    pub thermal: ThermalState, // Building, weather and comfort settings and the indoor temperature at midnight
+    pub water_tank: WaterTank, // The tank at midnight
+    pub policy: LoadShiftingPolicy, // Used by the scenarios that shift load
+    pub load_management: LoadManagement,
+    pub date: NaiveDate,
+}
+
+/// Longest date range analysed in one run
+pub const MAX_DAYS: i64 = 366;
+/// Longest range whose step-level records are returned along with the rollups
+pub const MAX_RECORD_DAYS: i64 = 31;
+// Share of the weekday EV charging need on weekends
+const WEEKEND_DRIVING: f64 = 0.5;
+
+/// Settings for one analysis run.
+pub struct AnalysisOptions {
+    pub seed: u64,
+    pub start: NaiveDate, // First day simulated
+    pub end: NaiveDate, // Last day simulated, inclusive
+    pub scenarios: Vec<Scenario>,
+    pub devices: Option<Vec<Device>>, // Simulated instead of the stored devices when set
+    pub tariff: Option<TariffSchedule>, // Used instead of the stored tariff when set
+    pub battery: Battery, // At the start of the first day
+    pub thermal: ThermalState, // Its weather is taken as midsummer and varied through the year
+    pub policy: LoadShiftingPolicy,
+    pub load_management: LoadManagement,
+    pub solar: Arc<SolarModel>,
+    pub reports_dir: PathBuf, // CSV files of this run are written here
+}
+
+/// Cost and energy totals of one scenario over a day or a month.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Rollup {
+    pub period: String, // `2024-01-31` for a day, `2024-01` for a month
+    pub scenario: String,
+    pub cost: f64,
+    pub grid_import: f64, // kWh
+    pub grid_export: f64, // kWh
+    pub solar_generation: f64, // kWh
+    pub home_consumption: f64, // kWh
+    pub self_consumption: f64, // kWh of solar used at home, directly or through the battery
+}
+
+pub struct AnalysisReport {
+    pub files: Vec<String>,
+    pub summary: String,
+    pub records: Vec<AnalysisRecord>, // Empty for ranges longer than `MAX_RECORD_DAYS`
+    pub daily: Vec<Rollup>,
+    pub monthly: Vec<Rollup>,
+}
+
+/// State a scenario carries from one simulated day into the next.
+struct DayEnd {
+    battery: Battery,
+    thermal: ThermalState,
+    water_tank: WaterTank,
+}
+
+/// Last day of a `day`, `week`, `month` or `year` starting on `start`.
+pub fn period_end(start: NaiveDate, period: &str) -> Option<NaiveDate> {
+    let end = match period {
+        "day" => start,
+        "week" => start + chrono::Duration::days(6),
+        "month" => start.checked_add_months(Months::new(1))? - chrono::Duration::days(1),
+        "year" => {
+            // A year from 29 February runs to 28 February
+            let next = start.with_year(start.year() + 1)
+                .or_else(|| NaiveDate::from_ymd_opt(start.year() + 1, 3, 1))?;
+            next - chrono::Duration::days(1)
+        }
+        _ => return None,
+    };
+    Some(end)
+}
+
+/// Runs every scenario for each day from `start` to `end`. Battery, house temperature and
+/// hot water tank carry over from one day to the next. All random draws come from `seed`, so
+/// two runs with the same seed, devices and tariff produce identical reports.
+pub async fn run_analysis(pool: &SqlitePool, options: AnalysisOptions) -> Result<AnalysisReport, Box<dyn Error>> {
+    let days = (options.end - options.start).num_days() + 1;
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(format!("The date range must cover 1 to {} days", MAX_DAYS).into());
+    }
+
+    let scenarios = options.scenarios;
+    if scenarios.is_empty() || scenarios.iter().enumerate().any(|(i, s)| scenarios[..i].contains(s)) {
+        return Err("scenarios must name at least one scenario, each at most once".into());
+    }
     let mut file_paths = Vec::new();
-    let mut summary = String::new();
+    let mut summary = format!("Period: {} to {} ({} days)\n", options.start, options.end, days);
     let mut all_records = Vec::new();
+    let (mut daily, mut monthly) = (Vec::new(), Vec::new());
 
-    // Fetch devices from DB
-    let devices = sqlx::query_as!(
-        Device,
-        "SELECT id, name, device_type, power_rating, is_on, priority FROM devices"
-    )
-    .fetch_all(pool)
-    .await?;
+    // Fetch devices from DB unless the run brings its own
+    let devices = match options.devices {
+        Some(devices) => devices,
+        None => sqlx::query_as!(
+            Device,
+            "SELECT id, name, device_type, power_rating, is_on, priority FROM devices"
+        )
+        .fetch_all(pool)
+        .await?,
+    };
 
-    // Pre-calculate Solar Profile for consistency
-    let mut solar_profile = Vec::new();
-    let mut rng = rand::rng();
-    for step in 0..48 {
-        let hour = step as f64 / 2.0;
-        let solar_potential = if hour > 6.0 && hour < 18.0 {
-            let peak = 2.0;
-            let x = (hour - 12.0) / 3.0;
-            peak * (-x * x).exp()
-        } else {
-            0.0
-        };
-        let solar_generation = (solar_potential * rng.random_range(0.8..1.0)).max(0.0);
-        solar_profile.push(solar_generation);
-    }
+    let tariff = match options.tariff {
+        Some(tariff) => tariff,
+        None => TariffSchedule::load(pool).await.map_err(|e| e as Box<dyn Error>)?,
+    };
+    // Stored charging sessions are not replayed: every charger that is switched on is assumed to
+    // serve a daily evening commute, and chargers that are off are left out
+    let commutes: Vec<EvDemand> = devices.iter()
+        .filter(|d| d.device_type == EV_CHARGER && d.is_on)
+        .map(EvDemand::evening_commute)
+        .collect();
 
     // Ensure reports directory exists
-    let reports_dir = "reports";
-    if !Path::new(reports_dir).exists() {
-        fs::create_dir(reports_dir)?;
+    let reports_dir = &options.reports_dir;
+    fs::create_dir_all(reports_dir)?;
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut state: Vec<DayEnd> = scenarios.iter()
+        .map(|_| DayEnd {
+            battery: options.battery.clone(),
+            thermal: ThermalState { indoor_temp: options.thermal.comfort.setpoint, ..options.thermal.clone() },
+            water_tank: WaterTank::default(),
+        })
+        .collect();
+    let mut records: Vec<Vec<AnalysisRecord>> = vec![Vec::new(); scenarios.len()];
+    let mut totals = vec![(0.0, 0.0); scenarios.len()]; // Cost and grid import (kWh)
+    let mut rebound = vec![0.0f64; scenarios.len()];
+
+    for date in options.start.iter_days().take(days as usize) {
+        // Every scenario sees the same weather and household behaviour on a given day
+        let solar_profile = generate_solar_profile(&mut rng, &options.solar, date);
+        let usage = usage_profile(date);
+        let weekend = matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+        let ev_demands: Vec<EvDemand> = commutes.iter()
+            .map(|demand| EvDemand {
+                required_kwh: if weekend { demand.required_kwh * WEEKEND_DRIVING } else { demand.required_kwh },
+                ..demand.clone()
+            })
+            .collect();
+        let weather = options.thermal.weather.seasonal(date.ordinal(), options.solar.array.latitude_deg);
+
+        for (i, &scenario) in scenarios.iter().enumerate() {
+            let inputs = DayInputs {
+                devices: &devices,
+                solar_profile: &solar_profile,
+                usage: &usage,
+                tariff: &tariff,
+                battery: &state[i].battery,
+                ev_demands: &ev_demands,
+                thermal: ThermalState { weather: weather.clone(), ..state[i].thermal.clone() },
+                water_tank: state[i].water_tank.clone(),
+                policy: options.policy.clone(),
+                load_management: options.load_management.clone(),
+                date,
+            };
+            let (day_records, cost, grid_import, _consumption, end) = simulate_day(scenario, &inputs);
+            rebound[i] = rebound[i].max(rebound_peak(&day_records, &inputs));
+            totals[i].0 += cost;
+            totals[i].1 += grid_import;
+            records[i].extend(day_records);
+            state[i] = end;
+        }
     }
 
-    for scenario in scenarios {
-        let (records, total_cost, total_grid_import, _total_consumption) = simulate_day(scenario, &devices, &solar_profile);
-        
-        let filename = format!("{}/analysis_{:?}.csv", reports_dir, scenario);
+    for ((scenario, records), ((total_cost, total_grid_import), rebound)) in scenarios.into_iter().zip(records).zip(totals.into_iter().zip(rebound)) {
+        let filename = reports_dir.join(format!("analysis_{:?}.csv", scenario)).display().to_string();
         let mut wtr = csv::Writer::from_path(&filename)?;
         
         for record in &records {
@@ -74,87 +250,314 @@ pub async fn run_analysis(pool: &SqlitePool) -> Result<(Vec<String>, String, Vec
         
         file_paths.push(filename);
         summary.push_str(&format!("\nScenario: {:?}\nTotal Cost: ${:.2}\nTotal Grid Import: {:.2} kWh\n", scenario, total_cost, total_grid_import));
-        all_records.extend(records);
+        summary.push_str(&format!("Rebound Peak: {:.2} kW\n", rebound));
+        let scenario_daily = rollups(&records, |date| date.to_string());
+        if scenario.has_solar() {
+            let solar: f64 = scenario_daily.iter().map(|d| d.solar_generation).sum();
+            let used: f64 = scenario_daily.iter().map(|d| d.self_consumption).sum();
+            let share = if solar > 0.0 { used / solar * 100.0 } else { 0.0 };
+            summary.push_str(&format!("Self-Consumption: {:.2} kWh ({:.0}% of solar)\n", used, share));
+        }
+        if scenario.has_battery() {
+            let discharged: f64 = records.iter().map(|r| r.battery_discharge * 0.5).sum();
+            summary.push_str(&format!("Battery Discharge: {:.2} kWh\n", discharged));
+        }
+        monthly.extend(rollups(&records, |date| date.format("%Y-%m").to_string()));
+        daily.extend(scenario_daily);
+        if days <= MAX_RECORD_DAYS {
+            all_records.extend(records);
+        }
     }
 
-    Ok((file_paths, summary, all_records))
+    for (name, rollups) in [("daily", &daily), ("monthly", &monthly)] {
+        let filename = reports_dir.join(format!("analysis_{}.csv", name)).display().to_string();
+        let mut wtr = csv::Writer::from_path(&filename)?;
+        for rollup in rollups {
+            wtr.serialize(rollup)?;
+        }
+        wtr.flush()?;
+        file_paths.push(filename);
+    }
+
+    Ok(AnalysisReport { files: file_paths, summary, records: all_records, daily, monthly })
+}
+
+/// Share of the appliance load in use at each step of `date`. Less runs overnight and, on
+/// weekdays, while the household is out at work or school.
+fn usage_profile(date: NaiveDate) -> Vec<f64> {
+    let weekend = matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+    (0..48)
+        .map(|step| {
+            let hour = step as f64 / 2.0;
+            if !(7.0..23.0).contains(&hour) {
+                0.5
+            } else if !weekend && (9.0..17.0).contains(&hour) {
+                0.3
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+/// Sums a scenario's time-ordered step records into one rollup per period, where `period`
+/// names the period a date belongs to.
+fn rollups(records: &[AnalysisRecord], period: impl Fn(NaiveDate) -> String) -> Vec<Rollup> {
+    let mut rollups: Vec<Rollup> = Vec::new();
+    for record in records {
+        let name = period(record.date);
+        if rollups.last().is_none_or(|last| last.period != name) {
+            rollups.push(Rollup { period: name, scenario: record.scenario.clone(), ..Rollup::default() });
+        }
+        if let Some(rollup) = rollups.last_mut() {
+            rollup.cost += record.cost;
+            rollup.grid_import += record.grid_import * 0.5;
+            rollup.grid_export += record.grid_export * 0.5;
+            rollup.solar_generation += record.solar_generation * 0.5;
+            rollup.home_consumption += record.home_consumption * 0.5;
+            rollup.self_consumption += (record.solar_generation - record.grid_export).max(0.0) * 0.5;
+        }
+    }
+    rollups
+}
+
+/// Highest grid import (kW) between the end of the day's last peak window and midnight,
+/// which is where restoring shed load shows up. 0 if the day has no peak window.
+fn rebound_peak(records: &[AnalysisRecord], inputs: &DayInputs) -> f64 {
+    let midnight = inputs.date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let last_peak = (0..records.len())
+        .rev()
+        .find(|&step| inputs.policy.is_peak(inputs.tariff, midnight + chrono::Duration::minutes(30 * step as i64)));
+    match last_peak {
+        Some(step) => records[step + 1..].iter().map(|r| r.grid_import).fold(0.0, f64::max),
+        None => 0.0,
+    }
+}
+
+fn generate_solar_profile(rng: &mut impl Rng, solar: &SolarModel, date: NaiveDate) -> Vec<f64> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let mut solar_profile = Vec::new();
+    for step in 0..48 {
+        let solar_potential = solar.generation(midnight + chrono::Duration::minutes(30 * step));
+        let solar_generation = (solar_potential * rng.random_range(0.8..1.0)).max(0.0);
+        solar_profile.push(solar_generation);
+    }
+    solar_profile
 }
 
-fn simulate_day(scenario: Scenario, devices: &[Device], solar_profile: &[f64]) -> (Vec<AnalysisRecord>, f64, f64, f64) {
+/// EV charging load (kW) per step. Smart scenarios follow the cost-minimizing schedule;
+/// the others charge at full rate from the moment the car is plugged in.
+fn ev_load_profile(scenario: Scenario, inputs: &DayInputs, other_load: f64) -> Vec<f64> {
+    let mut load = vec![0.0; 48];
+    for demand in inputs.ev_demands {
+        let window = demand.arrival_step.min(48)..demand.departure_step.min(48);
+        let power: Vec<f64> = if scenario.shifts_load() {
+            let slots: Vec<ChargeSlot> = window.clone()
+                .map(|step| {
+                    let solar = if scenario.has_solar() { inputs.solar_profile.get(step).copied().unwrap_or(0.0) } else { 0.0 };
+                    ChargeSlot { rate: inputs.tariff.rate_at(step as f64 / 2.0), solar_surplus: solar - other_load }
+                })
+                .collect();
+            ev::schedule_charging(&slots, demand.required_kwh, demand.max_rate_kw, 0.5, 0.0)
+        } else {
+            let mut remaining = demand.required_kwh;
+            window.clone()
+                .map(|_| {
+                    let kw = demand.max_rate_kw.min(remaining / 0.5);
+                    remaining -= kw * 0.5;
+                    kw
+                })
+                .collect()
+        };
+        for (step, kw) in window.zip(power) {
+            load[step] += kw;
+        }
+    }
+    load
+}
+
+/// Simulates one day of `scenario`, returning the step records, total cost, grid import (kWh)
+/// and consumption (kWh), and the state the next day starts from.
+fn simulate_day(scenario: Scenario, inputs: &DayInputs) -> (Vec<AnalysisRecord>, f64, f64, f64, DayEnd) {
+    let DayInputs { devices, solar_profile, tariff, .. } = *inputs;
     let mut records = Vec::new();
     let mut total_cost = 0.0;
     let mut total_consumption = 0.0;
     let mut total_grid_import = 0.0;
     
-    // Calculate potential load from currently ON devices
-    let active_devices: Vec<&Device> = devices.iter().filter(|d| d.is_on).collect();
+    // Calculate potential load from currently ON devices. EVs with a charging need are scheduled
+    // separately and HVAC and water heater load follow their thermal models.
+    let active_devices: Vec<&Device> = devices.iter()
+        .filter(|d| d.is_on && d.device_type != HVAC && d.device_type != WATER_HEATER)
+        .filter(|d| !inputs.ev_demands.iter().any(|e| e.device_id == d.id))
+        .collect();
     let total_potential_load: f64 = active_devices.iter().map(|d| d.power_rating).sum();
+    let rating_of = |device_type: &str| -> f64 {
+        devices.iter().filter(|d| d.is_on && d.device_type == device_type).map(|d| d.power_rating).sum()
+    };
+    let (hvac_rating, water_heater_rating) = (rating_of(HVAC), rating_of(WATER_HEATER));
+    // Load management switches appliances off one by one, lowest priority first, as the live
+    // simulator does. HVAC, EV charging and water heating modulate as a group each, ranked by
+    // their most important device.
+    let priority_of = |device_type: &str| -> i64 {
+        devices.iter().filter(|d| d.is_on && d.device_type == device_type).map(|d| d.priority).max().unwrap_or(0)
+    };
+    let group = |priority: i64, power: f64| Load { priority, power, modulating: true, protected: false };
+    let ev_load = ev_load_profile(scenario, inputs, 0.1 + total_potential_load);
+    let mut thermal = inputs.thermal.clone();
+    let mut tank = inputs.water_tank.clone();
 
     // Track deferred energy for SmartShift
+    let policy = &inputs.policy;
+    let shifting = scenario.shifts_load() && policy.enabled;
+    let sheddable: Vec<&Device> = active_devices.iter().copied().filter(|d| policy.sheds(d)).collect();
+    // Making up deferred energy ranks with the devices it was deferred from
+    let catch_up_priority = sheddable.iter().map(|d| d.priority).max().unwrap_or(0);
     let mut deferred_energy = 0.0; // kWh
+    let mut waiting: Vec<&Device> = Vec::new(); // Shed devices not yet restored
+    let mut last_restore: Option<usize> = None;
+    let mut waiting_since: Option<usize> = None; // Step the next device to restore started waiting
+    let restore_interval_steps = (policy.restore_interval_minutes as f64 / 30.0).ceil() as usize;
+    let max_delay_steps = (MAX_RESTORE_DELAY_MINUTES as f64 / 30.0).ceil() as usize;
+    let midnight = inputs.date.and_hms_opt(0, 0, 0).unwrap_or_default();
+
+    // The day starts from the battery as the previous day left it (the configured battery on the
+    // first day); the state at midnight is handed on to the next day
+    let mut battery = inputs.battery.clone();
 
     // Simulate 24 hours in 30-minute intervals (48 steps)
-    for step in 0..48 {
+    for (step, &solar) in solar_profile.iter().enumerate().take(48) {
         let hour = step as f64 / 2.0;
-        let is_peak = hour >= 18.0 && hour < 22.0;
+        let is_peak = tariff.is_peak(hour);
+        let time = midnight + chrono::Duration::minutes(30 * step as i64);
+        // Peak hours of the load-shifting policy, which drive shedding, pre-conditioning and
+        // the water heater as they do live
+        let shedding = policy.is_peak(tariff, time);
+        let usage = inputs.usage.get(step).copied().unwrap_or(1.0);
         
         // Solar Generation
-        let solar_generation = if matches!(scenario, Scenario::Baseline) {
-            0.0 // Baseline: No solar, pure grid import
+        let solar_generation = if scenario.has_solar() {
+            solar
         } else {
-            solar_profile[step]
+            0.0 // Baseline: No solar, pure grid import
         };
 
         // Base Load
         let base_load = 0.1; // 100W base load
+
+        // Smart scenarios pre-cool/pre-heat ahead of the peak and coast through it, as long as
+        // load shifting is enabled
+        let mode = if shifting { thermal.mode(time, 0.5, |t| policy.is_peak(tariff, t)) } else { HvacMode::Hold };
+        let heat = thermal.hvac_heat(hour, 0.5, mode, hvac_rating);
+        let hvac_load = thermal.electric_power(heat);
+
+        // Import before the battery and water heater, given the appliance load
+        let import_with = |appliance_load: f64| (base_load + appliance_load + hvac_load + ev_load[step] - solar_generation).max(0.0);
         
-        // Appliance Load Logic
-        let mut appliance_load = 0.0;
+        // Appliance Logic: which active devices run, plus deferred energy being made up
+        let mut stopped: Vec<&Device> = Vec::new();
+        let mut catch_up = 0.0; // kW
 
-        match scenario {
-            Scenario::Baseline | Scenario::Solar => {
-                // Standard operation: All active devices run all day
-                appliance_load = total_potential_load;
-            },
-            Scenario::SmartShift => {
-                if is_peak {
-                    // During peak: Turn off low priority devices and defer their energy
-                    for device in &active_devices {
-                        if device.priority < 2 {
-                            // Shifted (Turned Off)
-                            // Add to deferred energy. Power * Time (0.5h)
-                            deferred_energy += device.power_rating * 0.5;
-                        } else {
-                            appliance_load += device.power_rating;
-                        }
-                    }
-                } else {
-                    // Off-peak: Run standard load + Rebound deferred energy
-                    appliance_load = total_potential_load;
-                    
-                    // If we are AFTER peak (e.g., after 22:00), try to consume deferred energy
-                    if hour >= 22.0 && deferred_energy > 0.0 {
-                        // We must consume all deferred energy before midnight to ensure fair comparison (same total work)
-                        // Calculate remaining steps (including this one)
-                        let remaining_steps = 48 - step;
-                        
-                        // Distribute remaining energy evenly across remaining steps
-                        // This simulates running the deferred appliances in parallel or faster
-                        let energy_per_step = deferred_energy / remaining_steps as f64;
-                        
-                        appliance_load += energy_per_step / 0.5; // Convert energy back to power (kW)
-                        
-                        // We don't subtract from deferred_energy here because we recalculate 'remaining' each step
-                        // Actually, simpler: just take the chunk we decided to use.
-                        deferred_energy -= energy_per_step;
+        if shifting && shedding {
+            // During peak: Turn off low priority devices and defer their energy
+            waiting = sheddable.clone();
+            stopped = sheddable.clone();
+            waiting_since = None;
+            // Add to deferred energy. Power * Time (0.5h)
+            deferred_energy += sheddable.iter().map(|d| d.power_rating * usage * 0.5).sum::<f64>();
+        } else if shifting && deferred_energy > 0.0 {
+            // Off-peak after a peak (deferred energy only builds up during it)
+            match policy.restore {
+                RestoreStrategy::Manual => {
+                    // Shed devices stay off for the rest of the day and their energy is never made up
+                    stopped = sheddable.clone();
+                }
+                RestoreStrategy::Staggered => {
+                    // Shed devices come back one per interval while import stays under the cap; until
+                    // then they keep deferring energy
+                    let running: f64 = active_devices.iter()
+                        .filter(|d| !waiting.iter().any(|w| w.id == d.id))
+                        .map(|d| d.power_rating * usage)
+                        .sum();
+                    let due = last_restore.is_none_or(|last| step - last >= restore_interval_steps);
+                    let overdue = step - *waiting_since.get_or_insert(step) >= max_delay_steps;
+                    if let Some(device) = policy.next_restore(&waiting, import_with(running), overdue).filter(|_| due) {
+                        waiting.retain(|d| d.id != device.id);
+                        last_restore = Some(step);
+                        waiting_since = Some(step);
                     }
+                    stopped = waiting.clone();
+                    deferred_energy += waiting.iter().map(|d| d.power_rating * usage * 0.5).sum::<f64>();
+
+                    // Catch up at the same pace as an immediate restore, limited to the headroom under
+                    // the cap. Whatever does not fit before midnight is not made up.
+                    let running: f64 = active_devices.iter()
+                        .filter(|d| !stopped.iter().any(|s| s.id == d.id))
+                        .map(|d| d.power_rating * usage)
+                        .sum();
+                    let headroom = (policy.restore_import_cap_kw - import_with(running)).max(0.0);
+                    catch_up = (deferred_energy / (48 - step) as f64 / 0.5).min(headroom);
+                    deferred_energy -= catch_up * 0.5;
+                }
+                RestoreStrategy::Immediate => {
+                    // We must consume all deferred energy before midnight to ensure fair comparison (same total work)
+                    // Distribute remaining energy evenly across remaining steps (including this one)
+                    // This simulates running the deferred appliances in parallel or faster
+                    let remaining_steps = 48 - step;
+                    let energy_per_step = deferred_energy / remaining_steps as f64;
+                    catch_up = energy_per_step / 0.5; // Convert energy back to power (kW)
+                    deferred_energy -= energy_per_step;
                 }
             }
         }
 
-        let home_consumption = base_load + appliance_load;
+        // Standard operation: every other active device runs whenever it is in use
+        let running: Vec<&Device> = active_devices.iter().copied()
+            .filter(|d| !stopped.iter().any(|s| s.id == d.id))
+            .collect();
+        let appliance_load = running.iter().map(|d| d.power_rating * usage).sum::<f64>() + catch_up;
+
+        // Smart scenarios store solar surplus in the hot water tank and avoid heating at peak, as
+        // long as load shifting is enabled
+        let other_load = base_load + appliance_load + hvac_load + ev_load[step];
+        let strategy = if shifting {
+            HeatingStrategy::Smart { is_peak: shedding, solar_surplus: solar_generation - other_load }
+        } else {
+            HeatingStrategy::Thermostat
+        };
+        let draw = water_heater::hot_water_draw(hour, 0.5);
+        let water_heating = tank.heating_power(0.5, water_heater_rating, draw, strategy);
+
+        // Stay within the import limit; curtailed energy is not made up
+        let mut loads: Vec<Load> = running.iter()
+            .map(|d| Load { priority: d.priority, power: d.power_rating * usage, modulating: false, protected: false })
+            .collect();
+        loads.push(group(catch_up_priority, catch_up));
+        let appliances = loads.len();
+        loads.extend([
+            group(priority_of(HVAC), hvac_load),
+            group(priority_of(EV_CHARGER), ev_load[step]),
+            group(priority_of(WATER_HEATER), water_heating),
+        ]);
+        let allowed = inputs.load_management.allocate(&loads, base_load - solar_generation);
+        let appliance_load: f64 = allowed[..appliances].iter().sum();
+        let (hvac_allowed, ev_allowed, water_heating) = (allowed[appliances], allowed[appliances + 1], allowed[appliances + 2]);
+        thermal.advance(hour, if hvac_load > 0.0 { heat * hvac_allowed / hvac_load } else { heat }, 0.5);
+        tank.advance(water_heating, draw, 0.5);
+
+        let home_consumption = base_load + appliance_load + hvac_allowed + ev_allowed + water_heating;
         let net_energy = solar_generation - home_consumption;
-        
+
+        // Battery: charge on surplus, discharge on deficit (same rule as the live simulator)
+        let (battery_charge, battery_discharge) = if !scenario.has_battery() {
+            (0.0, 0.0)
+        } else if net_energy > 0.0 {
+            (battery.charge(net_energy, 0.5), 0.0)
+        } else {
+            (0.0, battery.discharge(-net_energy, 0.5))
+        };
+        let net_energy = net_energy - battery_charge + battery_discharge;
+
         let (grid_import, grid_export) = if net_energy > 0.0 {
             (0.0, net_energy)
         } else {
@@ -162,7 +565,7 @@ fn simulate_day(scenario: Scenario, devices: &[Device], solar_profile: &[f64]) -
         };
 
         // Calculate Cost
-        let rate = if is_peak { 0.30 } else { 0.10 };
+        let rate = tariff.rate_at(hour);
         let cost = grid_import * rate * 0.5; // kWh * rate (0.5h interval)
         
         total_cost += cost;
@@ -170,18 +573,23 @@ fn simulate_day(scenario: Scenario, devices: &[Device], solar_profile: &[f64]) -
         total_grid_import += grid_import * 0.5;
 
         records.push(AnalysisRecord {
+            date: inputs.date,
             time_step: format!("{:02}:{:02}", hour.trunc() as i32, (hour.fract() * 60.0) as i32),
             scenario: format!("{:?}", scenario),
             solar_generation,
             home_consumption,
             grid_import,
             grid_export,
+            battery_charge,
+            battery_discharge,
+            battery_soc: if scenario.has_battery() { battery.soc } else { 0.0 },
             cost,
             is_peak,
         });
     }
     
-    (records, total_cost, total_grid_import, total_consumption)
+    let end = DayEnd { battery, thermal, water_tank: tank };
+    (records, total_cost, total_grid_import, total_consumption, end)
 }
 
 #[cfg(test)]
@@ -195,23 +603,91 @@ mod tests {
         ]
     }
 
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()
+    }
+
+    fn day<'a>(devices: &'a [Device], solar_profile: &'a [f64], tariff: &'a TariffSchedule, battery: &'a Battery) -> DayInputs<'a> {
+        DayInputs {
+            devices,
+            solar_profile,
+            usage: &[1.0; 48],
+            tariff,
+            battery,
+            ev_demands: &[],
+            thermal: ThermalState::default(),
+            water_tank: WaterTank::default(),
+            policy: LoadShiftingPolicy::default(),
+            load_management: LoadManagement::default(),
+            date: date(),
+        }
+    }
+
     #[test]
     fn test_simulation_runs() {
         let devices = get_mock_devices();
         let solar_profile = vec![0.0; 48];
-        let (records, cost, grid_import, consumption) = simulate_day(Scenario::Baseline, &devices, &solar_profile);
+        let (records, cost, grid_import, consumption, _) = simulate_day(Scenario::Baseline, &day(&devices, &solar_profile, &TariffSchedule::default(), &Battery::default()));
         assert_eq!(records.len(), 48);
         assert!(cost > 0.0);
         assert!(grid_import > 0.0);
         assert!(consumption > 0.0);
     }
 
+    #[test]
+    fn test_baseline_cost_golden() {
+        // 1.6 kW all day priced at the default tariff
+        let devices = get_mock_devices();
+        let solar_profile = vec![0.0; 48];
+        let (_, cost, grid_import, _, _) = simulate_day(Scenario::Baseline, &day(&devices, &solar_profile, &TariffSchedule::default(), &Battery::default()));
+        assert!((cost - 6.24).abs() < 1e-9, "cost was {}", cost);
+        assert!((grid_import - 38.4).abs() < 1e-9, "grid import was {}", grid_import);
+    }
+
+    #[test]
+    fn test_same_seed_reproduces_run() {
+        let devices = get_mock_devices();
+        let tariff = TariffSchedule::default();
+        let run = |seed| {
+            let solar_profile = generate_solar_profile(&mut StdRng::seed_from_u64(seed), &SolarModel::default(), date());
+            let (records, cost, _, _, _) = simulate_day(Scenario::SmartShift, &day(&devices, &solar_profile, &tariff, &Battery::default()));
+            (records.iter().map(|r| r.grid_import).collect::<Vec<_>>(), cost)
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_battery_scenarios() {
+        let devices = get_mock_devices();
+        let tariff = TariffSchedule::default();
+        let battery = Battery::default();
+        let solar_profile = generate_solar_profile(&mut StdRng::seed_from_u64(1), &SolarModel::default(), date()).iter().map(|s| s * 3.0).collect::<Vec<_>>();
+
+        let (_, cost_solar, _, consumption_solar, _) = simulate_day(Scenario::Solar, &day(&devices, &solar_profile, &tariff, &battery));
+        let (records, cost_battery, _, consumption_battery, _) = simulate_day(Scenario::SolarBattery, &day(&devices, &solar_profile, &tariff, &battery));
+
+        assert!(cost_battery < cost_solar);
+        assert!((consumption_solar - consumption_battery).abs() < 0.001);
+        assert!(records.iter().any(|r| r.battery_charge > 0.0));
+        assert!(records.iter().any(|r| r.battery_discharge > 0.0));
+        assert!(records.iter().all(|r| (battery.min_soc..=battery.max_soc).contains(&r.battery_soc)));
+        // Energy balance holds at every step
+        for r in &records {
+            let balance = r.solar_generation + r.grid_import + r.battery_discharge
+                - r.home_consumption - r.grid_export - r.battery_charge;
+            assert!(balance.abs() < 1e-9);
+        }
+    }
+
     #[test]
     fn test_scenarios_differ() {
         let devices = get_mock_devices();
         let solar_profile = vec![1.0; 48]; // High solar for testing
-        let (_, cost_baseline, _, consumption_baseline) = simulate_day(Scenario::Baseline, &devices, &solar_profile);
-        let (_, cost_smart, _, consumption_smart) = simulate_day(Scenario::SmartShift, &devices, &solar_profile);
+        let tariff = TariffSchedule::default();
+        let (_, cost_baseline, _, consumption_baseline, _) = simulate_day(Scenario::Baseline, &day(&devices, &solar_profile, &tariff, &Battery::default()));
+        let (_, cost_smart, _, consumption_smart, _) = simulate_day(Scenario::SmartShift, &day(&devices, &solar_profile, &tariff, &Battery::default()));
         
         // SmartShift should be cheaper than Baseline (due to solar + shifting)
         assert!(cost_smart <= cost_baseline);
@@ -221,4 +697,183 @@ mod tests {
         assert!((consumption_baseline - consumption_smart).abs() < 0.001, 
             "Total consumption should be equal! Baseline: {}, Smart: {}", consumption_baseline, consumption_smart);
     }
+
+    #[test]
+    fn test_smart_ev_charging() {
+        let devices = vec![
+            Device { id: 1, name: "Car".to_string(), device_type: EV_CHARGER.to_string(), power_rating: 7.0, is_on: false, priority: 2 },
+        ];
+        let demands = vec![EvDemand::evening_commute(&devices[0])];
+        let solar_profile = vec![0.0; 48];
+        let tariff = TariffSchedule::default();
+        let battery = Battery::default();
+        let inputs = DayInputs { ev_demands: &demands, ..day(&devices, &solar_profile, &tariff, &battery) };
+
+        let (dumb, cost_dumb, _, consumption_dumb, _) = simulate_day(Scenario::Baseline, &inputs);
+        let (smart, cost_smart, _, consumption_smart, _) = simulate_day(Scenario::SmartShift, &inputs);
+
+        // Both deliver the full 14 kWh (plus base load); plugging in at 18:00 charges at peak
+        assert!((consumption_dumb - consumption_smart).abs() < 1e-9);
+        assert!((consumption_dumb - (14.0 + 0.1 * 24.0)).abs() < 1e-9);
+        assert!(dumb[36].home_consumption > 7.0);
+        assert!(smart[36..42].iter().all(|r| r.home_consumption < 0.2));
+        assert!(cost_smart < cost_dumb);
+    }
+
+    #[test]
+    fn test_hvac_preconditioning() {
+        let devices = vec![
+            Device { id: 1, name: "HVAC".to_string(), device_type: HVAC.to_string(), power_rating: 3.0, is_on: true, priority: 3 },
+        ];
+        let solar_profile = vec![0.0; 48];
+        let tariff = TariffSchedule::default();
+        let battery = Battery::default();
+        let inputs = day(&devices, &solar_profile, &tariff, &battery);
+
+        let peak_import = |records: &[AnalysisRecord]| -> f64 {
+            records.iter().filter(|r| r.is_peak).map(|r| r.grid_import * 0.5).sum()
+        };
+        let (baseline, cost_baseline, _, _, _) = simulate_day(Scenario::Baseline, &inputs);
+        let (smart, cost_smart, _, _, _) = simulate_day(Scenario::SmartShift, &inputs);
+
+        // HVAC load follows the weather rather than the 3 kW rating
+        assert!(baseline.iter().any(|r| r.home_consumption < 3.0 && r.home_consumption > 0.1));
+        assert!(peak_import(&smart) < peak_import(&baseline));
+        assert!(cost_smart < cost_baseline);
+    }
+
+    #[test]
+    fn test_water_heater_soaks_up_solar() {
+        let devices = vec![
+            Device { id: 1, name: "Water Heater".to_string(), device_type: WATER_HEATER.to_string(), power_rating: 3.0, is_on: true, priority: 1 },
+        ];
+        let solar_profile: Vec<f64> = (0..48).map(|step| if (16..32).contains(&step) { 2.0 } else { 0.0 }).collect();
+        let tariff = TariffSchedule::default();
+        let battery = Battery::default();
+        let inputs = day(&devices, &solar_profile, &tariff, &battery);
+
+        let (solar, cost_solar, _, _, _) = simulate_day(Scenario::Solar, &inputs);
+        let (smart, cost_smart, _, _, _) = simulate_day(Scenario::SmartShift, &inputs);
+
+        let export = |records: &[AnalysisRecord]| -> f64 { records.iter().map(|r| r.grid_export).sum() };
+        assert!(export(&smart) < export(&solar));
+        assert!(smart.iter().filter(|r| r.is_peak).all(|r| r.home_consumption <= 0.1 + 1e-9));
+        assert!(cost_smart <= cost_solar);
+    }
+
+    #[test]
+    fn test_load_shifting_policy() {
+        use crate::load_shifting::PeakWindow;
+
+        let devices = get_mock_devices();
+        let solar_profile = vec![0.0; 48];
+        let tariff = TariffSchedule::default();
+        let battery = Battery::default();
+        let policy = LoadShiftingPolicy {
+            peak_windows: vec![PeakWindow { start_hour: 10.0, end_hour: 12.0, weekdays: Vec::new() }],
+            ..LoadShiftingPolicy::default()
+        };
+        let inputs = DayInputs { policy: policy.clone(), ..day(&devices, &solar_profile, &tariff, &battery) };
+
+        // The low-priority 1 kW device is shed in the configured window instead of the tariff peak
+        let (records, _, _, consumption, _) = simulate_day(Scenario::SmartShift, &inputs);
+        assert!((records[20].home_consumption - 0.6).abs() < 1e-9);
+        assert!((records[36].home_consumption - 0.6).abs() > 1e-9);
+        assert!((consumption - 1.6 * 24.0).abs() < 1e-9);
+
+        // With manual restore the shed device stays off and its energy is not made up
+        let manual = DayInputs { policy: LoadShiftingPolicy { restore: RestoreStrategy::Manual, ..policy }, ..inputs };
+        let (records, _, _, consumption, _) = simulate_day(Scenario::SmartShift, &manual);
+        assert!((records[30].home_consumption - 0.6).abs() < 1e-9);
+        assert!((consumption - (1.6 * 10.0 + 0.6 * 14.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_staggered_restore_caps_rebound() {
+        let devices = get_mock_devices();
+        let solar_profile = vec![0.0; 48];
+        let tariff = TariffSchedule::default();
+        let battery = Battery::default();
+        let immediate = day(&devices, &solar_profile, &tariff, &battery);
+        let staggered = DayInputs {
+            policy: LoadShiftingPolicy {
+                restore: RestoreStrategy::Staggered,
+                restore_import_cap_kw: 2.0,
+                ..LoadShiftingPolicy::default()
+            },
+            ..day(&devices, &solar_profile, &tariff, &battery)
+        };
+
+        // Immediate restore makes up the 4 kWh shed from 17:00-21:00 over the last three hours
+        let (records, _, _, _, _) = simulate_day(Scenario::SmartShift, &immediate);
+        assert!((rebound_peak(&records, &immediate) - (1.6 + 4.0 / 3.0)).abs() < 1e-9);
+
+        let (records, _, _, _, _) = simulate_day(Scenario::SmartShift, &staggered);
+        assert!((rebound_peak(&records, &staggered) - 2.0).abs() < 1e-9);
+        assert!(records.iter().skip(42).all(|r| r.grid_import <= 2.0 + 1e-9));
+    }
+
+    #[test]
+    fn test_import_limit() {
+        let mut devices = get_mock_devices();
+        devices[0].power_rating = 12.0;
+        let solar_profile = vec![0.0; 48];
+        let tariff = TariffSchedule::default();
+        let battery = Battery::default();
+
+        // The 12 kW appliance cannot run at 10 kW, so it is switched off altogether
+        let (records, _, _, _, _) = simulate_day(Scenario::Baseline, &day(&devices, &solar_profile, &tariff, &battery));
+        assert!(records.iter().all(|r| (r.grid_import - 0.6).abs() < 1e-9));
+
+        let unlimited = DayInputs {
+            load_management: LoadManagement { enabled: false, ..LoadManagement::default() },
+            ..day(&devices, &solar_profile, &tariff, &battery)
+        };
+        let (records, _, _, _, _) = simulate_day(Scenario::Baseline, &unlimited);
+        assert!(records.iter().all(|r| (r.grid_import - 12.6).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_multi_day_rollups() {
+        // 2024-01-06 is a Saturday: appliances are in use through the day
+        let (friday, saturday) = (date() + chrono::Duration::days(4), date() + chrono::Duration::days(5));
+        assert_eq!(usage_profile(friday)[24], 0.3);
+        assert_eq!(usage_profile(saturday)[24], 1.0);
+        assert_eq!(usage_profile(saturday)[2], 0.5);
+
+        assert_eq!(period_end(date(), "week"), Some(date() + chrono::Duration::days(6)));
+        assert_eq!(period_end(date(), "month"), NaiveDate::from_ymd_opt(2024, 1, 31));
+        assert_eq!(period_end(date(), "year"), NaiveDate::from_ymd_opt(2024, 12, 31));
+        let leap_day = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(period_end(leap_day, "year"), NaiveDate::from_ymd_opt(2025, 2, 28));
+        assert_eq!(period_end(leap_day, "month"), NaiveDate::from_ymd_opt(2024, 3, 28));
+        assert_eq!(period_end(date(), "fortnight"), None);
+
+        // Two days in January and one in February, with the battery carried over
+        let devices = get_mock_devices();
+        let solar_profile = vec![1.0; 48];
+        let tariff = TariffSchedule::default();
+        let mut battery = Battery::default();
+        let mut records = Vec::new();
+        for date in [date() + chrono::Duration::days(29), date() + chrono::Duration::days(30), date() + chrono::Duration::days(31)] {
+            let inputs = DayInputs { date, ..day(&devices, &solar_profile, &tariff, &battery) };
+            let (day_records, _, _, _, end) = simulate_day(Scenario::SolarBattery, &inputs);
+            records.extend(day_records);
+            battery = end.battery;
+        }
+        // The battery emptied on the first day has nothing left on the next
+        assert!(records[0].battery_discharge > 0.0);
+        assert!(records[48..].iter().all(|r| r.battery_discharge == 0.0));
+
+        let daily = rollups(&records, |date| date.to_string());
+        let monthly = rollups(&records, |date| date.format("%Y-%m").to_string());
+        assert_eq!(daily.iter().map(|d| d.period.as_str()).collect::<Vec<_>>(), ["2024-01-30", "2024-01-31", "2024-02-01"]);
+        assert_eq!(monthly.iter().map(|m| m.period.as_str()).collect::<Vec<_>>(), ["2024-01", "2024-02"]);
+        assert!((monthly[0].cost - daily[0].cost - daily[1].cost).abs() < 1e-9);
+        assert!((monthly[1].grid_import - daily[2].grid_import).abs() < 1e-9);
+        for d in &daily {
+            assert!((d.solar_generation - 24.0).abs() < 1e-9);
+            assert!((d.self_consumption - (d.solar_generation - d.grid_export)).abs() < 1e-9);
+        }
+    }
 }
@@ -1,23 +1,49 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    response::sse::{Event, KeepAlive, Sse},
     Json,
 };
-use crate::models::{EnergyData, Device};
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use crate::models::{Actor, ApplianceRun, Curtailment, EnergyData, Device, DeviceEvent, DeviceOverride, DeviceReading, EvSession, Tariff};
+use crate::live::{self, LiveEvent};
+use crate::events;
+use crate::overrides::DEFAULT_OVERRIDE_MINUTES;
+use crate::load_shifting::{LoadShiftingPolicy, PeakWindow, RestoreStrategy};
+use crate::load_management::LoadManagement;
+use crate::analysis::{AnalysisOptions, Scenario};
+use crate::battery::Battery;
+use crate::clock::{SimClock, MAX_STEP_MINUTES, MIN_TICK_INTERVAL_MS};
+use crate::dispatch::{DispatchState, DispatchStrategy};
+use crate::thermal::{Comfort, ThermalState};
+use crate::water_heater::WaterTank;
+use crate::appliance::{self, Program, PROGRAMS};
+use crate::tariff::TariffSchedule;
+use crate::forecast::{self, MAX_HORIZON_MINUTES};
+use crate::catalogue::{self, DeviceType, DEVICE_TYPES};
+use crate::telemetry::{self, Aggregation, Bucket, DeviceBucket, EnergyBucket};
+use chrono::{NaiveDate, NaiveDateTime, Timelike};
+use serde::Serialize;
 use serde::Deserialize;
 
+/// Manual on/off. The state holds against automated control until `until`, or for
+/// `for_minutes`, or by default for six simulated hours.
 #[derive(Deserialize)]
 pub struct DeviceControl {
     pub is_on: bool,
+    pub until: Option<NaiveDateTime>,
+    pub for_minutes: Option<i64>,
 }
 
 use crate::AppState;
-use std::time::Instant;
 
 pub async fn get_latest_energy(State(state): State<AppState>) -> Json<Option<EnergyData>> {
     let data = sqlx::query_as!(
         EnergyData,
         r#"
-        SELECT id, timestamp, grid_import, grid_export, solar_generation, battery_charge, battery_discharge, home_consumption, battery_soc
+        SELECT id, timestamp, grid_import, grid_export, solar_generation, battery_charge, battery_discharge, home_consumption, battery_soc, cost
         FROM energy_data
         ORDER BY id DESC
         LIMIT 1
@@ -30,6 +56,169 @@ pub async fn get_latest_energy(State(state): State<AppState>) -> Json<Option<Ene
     Json(data)
 }
 
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub bucket: Bucket,
+    #[serde(default)]
+    pub agg: Aggregation,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum EnergyHistory {
+    Raw(Vec<EnergyData>),
+    Aggregated(Vec<EnergyBucket>),
+}
+
+/// Resolves a history range. Without `to` the range ends at the newest sample; without
+/// `from` it covers the 24 hours before `to`. `None` when there is no data yet.
+async fn history_range(state: &AppState, from: Option<NaiveDateTime>, to: Option<NaiveDateTime>) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let to = match to {
+        Some(to) => to,
+        None => sqlx::query_scalar!("SELECT timestamp FROM energy_data ORDER BY timestamp DESC LIMIT 1")
+            .fetch_optional(&state.pool)
+            .await
+            .unwrap_or(None)?,
+    };
+    Some((from.unwrap_or(to - chrono::Duration::hours(24)), to))
+}
+
+/// Telemetry between `from` and `to` (inclusive). Without `to` the range ends at the
+/// newest sample; without `from` it covers the 24 hours before `to`.
+pub async fn get_energy_history(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<EnergyHistory> {
+    let Some((from, to)) = history_range(&state, query.from, query.to).await else {
+        return Json(EnergyHistory::Raw(Vec::new()));
+    };
+
+    let rows = sqlx::query_as!(
+        EnergyData,
+        r#"
+        SELECT id, timestamp, grid_import, grid_export, solar_generation, battery_charge, battery_discharge, home_consumption, battery_soc, cost
+        FROM energy_data
+        WHERE timestamp >= ? AND timestamp <= ?
+        ORDER BY timestamp
+        "#,
+        from,
+        to
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    match query.bucket {
+        Bucket::Raw => Json(EnergyHistory::Raw(rows)),
+        bucket => Json(EnergyHistory::Aggregated(telemetry::aggregate(&rows, bucket, query.agg))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeviceHistoryQuery {
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub bucket: Bucket,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum DeviceHistory {
+    Raw(Vec<DeviceReading>),
+    Aggregated(Vec<DeviceBucket>),
+}
+
+/// Metered consumption of one device, over the same range as `/api/energy/history`.
+pub async fn get_device_history(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(query): Query<DeviceHistoryQuery>,
+) -> Json<DeviceHistory> {
+    let Some((from, to)) = history_range(&state, query.from, query.to).await else {
+        return Json(DeviceHistory::Raw(Vec::new()));
+    };
+
+    let rows = sqlx::query_as!(
+        DeviceReading,
+        r#"
+        SELECT id AS "id!", device_id, timestamp, power, energy
+        FROM device_readings
+        WHERE device_id = ? AND timestamp >= ? AND timestamp <= ?
+        ORDER BY timestamp
+        "#,
+        id,
+        from,
+        to
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    match query.bucket {
+        Bucket::Raw => Json(DeviceHistory::Raw(rows)),
+        bucket => Json(DeviceHistory::Aggregated(telemetry::aggregate_device(&rows, bucket))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RangeQuery {
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct DeviceEnergy {
+    pub device_id: i64,
+    pub name: String,
+    pub device_type: String,
+    pub energy: f64, // kWh
+    pub peak_power: f64, // kW
+    pub share: f64, // Fraction of the energy metered across all devices
+}
+
+/// Energy drawn by each device over the range, largest consumer first.
+pub async fn get_device_breakdown(
+    State(state): State<AppState>,
+    Query(query): Query<RangeQuery>,
+) -> Json<Vec<DeviceEnergy>> {
+    let Some((from, to)) = history_range(&state, query.from, query.to).await else {
+        return Json(Vec::new());
+    };
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT d.id AS "device_id!", d.name AS "name!", d.device_type AS "device_type!",
+            COALESCE(SUM(r.energy), 0) AS "energy!: f64",
+            COALESCE(MAX(r.power), 0) AS "peak_power!: f64"
+        FROM devices d
+        LEFT JOIN device_readings r ON r.device_id = d.id AND r.timestamp >= ? AND r.timestamp <= ?
+        GROUP BY d.id
+        ORDER BY 4 DESC
+        "#,
+        from,
+        to
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    let total: f64 = rows.iter().map(|r| r.energy).sum();
+    Json(rows.into_iter()
+        .map(|r| DeviceEnergy {
+            device_id: r.device_id,
+            name: r.name,
+            device_type: r.device_type,
+            energy: r.energy,
+            peak_power: r.peak_power,
+            share: if total > 0.0 { r.energy / total } else { 0.0 },
+        })
+        .collect())
+}
+
 pub async fn get_devices(State(state): State<AppState>) -> Json<Vec<Device>> {
     let devices = sqlx::query_as!(
         Device,
@@ -42,15 +231,192 @@ pub async fn get_devices(State(state): State<AppState>) -> Json<Vec<Device>> {
     Json(devices)
 }
 
+pub async fn get_device_types() -> Json<&'static [DeviceType]> {
+    Json(DEVICE_TYPES)
+}
+
+/// Fields for creating or updating a device. Omitted fields fall back to the
+/// device's current values, or to the catalogue defaults for a new device.
+#[derive(Deserialize)]
+pub struct DeviceInput {
+    pub name: Option<String>,
+    pub device_type: Option<String>,
+    pub power_rating: Option<f64>,
+    pub priority: Option<i64>,
+}
+
+fn validate_device(device: &Device) -> Result<(), String> {
+    if device.name.trim().is_empty() {
+        return Err("Device name must not be empty".to_string());
+    }
+    if device.device_type.trim().is_empty() {
+        return Err("Device type must not be empty".to_string());
+    }
+    if device.power_rating < 0.0 || device.power_rating.is_nan() {
+        return Err(format!("Power rating must be non-negative, got {}", device.power_rating));
+    }
+    if device.priority < 0 {
+        return Err(format!("Priority must be non-negative, got {}", device.priority));
+    }
+    Ok(())
+}
+
+/// A new, switched-off device from `input`, with catalogue defaults for an omitted rating and priority.
+fn new_device(input: DeviceInput) -> Result<Device, String> {
+    let (Some(name), Some(device_type)) = (input.name, input.device_type) else {
+        return Err("name and device_type are required".to_string());
+    };
+
+    let defaults = catalogue::lookup(&device_type);
+    let Some(power_rating) = input.power_rating.or(defaults.map(|d| d.default_power_rating)) else {
+        return Err(format!("Unknown device type '{}': power_rating is required", device_type));
+    };
+
+    let device = Device {
+        id: 0,
+        name,
+        device_type,
+        power_rating,
+        is_on: false,
+        priority: input.priority.or(defaults.map(|d| d.default_priority)).unwrap_or(0),
+    };
+    validate_device(&device)?;
+    Ok(device)
+}
+
+pub async fn create_device(
+    State(state): State<AppState>,
+    Json(payload): Json<DeviceInput>,
+) -> Json<serde_json::Value> {
+    let mut device = match new_device(payload) {
+        Ok(device) => device,
+        Err(e) => return Json(serde_json::json!({ "success": false, "error": e })),
+    };
+
+    let result = sqlx::query!(
+        "INSERT INTO devices (name, device_type, power_rating, is_on, priority) VALUES (?, ?, ?, ?, ?)",
+        device.name,
+        device.device_type,
+        device.power_rating,
+        device.is_on,
+        device.priority
+    )
+    .execute(&state.pool)
+    .await;
+
+    match result {
+        Ok(done) => {
+            device.id = done.last_insert_rowid();
+            Json(serde_json::json!({ "success": true, "device": device }))
+        }
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+pub async fn update_device(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<DeviceInput>,
+) -> Json<serde_json::Value> {
+    let existing = sqlx::query_as!(
+        Device,
+        "SELECT id, name, device_type, power_rating, is_on, priority FROM devices WHERE id = ?",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+
+    let mut device = match existing {
+        Ok(Some(device)) => device,
+        Ok(None) => return Json(serde_json::json!({ "success": false, "error": format!("Device {} not found", id) })),
+        Err(e) => return Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    };
+
+    if let Some(name) = payload.name {
+        device.name = name;
+    }
+    if let Some(device_type) = payload.device_type {
+        device.device_type = device_type;
+    }
+    if let Some(power_rating) = payload.power_rating {
+        device.power_rating = power_rating;
+    }
+    if let Some(priority) = payload.priority {
+        device.priority = priority;
+    }
+    if let Err(e) = validate_device(&device) {
+        return Json(serde_json::json!({ "success": false, "error": e }));
+    }
+
+    let result = sqlx::query!(
+        "UPDATE devices SET name = ?, device_type = ?, power_rating = ?, priority = ? WHERE id = ?",
+        device.name,
+        device.device_type,
+        device.power_rating,
+        device.priority,
+        id
+    )
+    .execute(&state.pool)
+    .await;
+
+    match result {
+        Ok(_) => Json(serde_json::json!({ "success": true, "device": device })),
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+pub async fn delete_device(State(state): State<AppState>, Path(id): Path<i64>) -> Json<bool> {
+    let result = sqlx::query!("DELETE FROM devices WHERE id = ?", id)
+        .execute(&state.pool)
+        .await;
+
+    match result {
+        Ok(done) => Json(done.rows_affected() > 0),
+        Err(_) => Json(false),
+    }
+}
+
 pub async fn control_device(
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Json(payload): Json<DeviceControl>,
-) -> Json<bool> {
-    // Record user override
-    {
-        let mut overrides = state.user_overrides.lock().await;
-        overrides.insert(id, Instant::now());
+) -> Json<serde_json::Value> {
+    let device = sqlx::query_as!(
+        Device,
+        "SELECT id, name, device_type, power_rating, is_on, priority FROM devices WHERE id = ?",
+        id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+    let Ok(Some(device)) = device else {
+        return Json(serde_json::json!({ "success": false, "error": format!("Device {} not found", id) }));
+    };
+
+    let now = state.clock.snapshot().await.current_time;
+    let expires_at = match payload.until {
+        Some(until) => Some(until),
+        None => chrono::TimeDelta::try_minutes(payload.for_minutes.unwrap_or(DEFAULT_OVERRIDE_MINUTES))
+            .and_then(|duration| now.checked_add_signed(duration)),
+    };
+    let Some(expires_at) = expires_at else {
+        return Json(serde_json::json!({ "success": false, "error": "for_minutes is out of range" }));
+    };
+    if expires_at <= now {
+        return Json(serde_json::json!({
+            "success": false,
+            "error": format!("The override must expire after the current simulated time {}", now)
+        }));
+    }
+
+    if let Err(e) = record_override(&state.pool, id, payload.is_on, now, expires_at).await {
+        tracing::warn!("Failed to store override for device {}: {}", id, e);
+        return Json(serde_json::json!({ "success": false, "error": e.to_string() }));
+    }
+    if appliance::program_for(&device.device_type).is_some() {
+        if let Err(e) = interrupt_appliance_runs(&state.pool, id, now, expires_at).await {
+            tracing::warn!("Failed to interrupt the program of device {}: {}", id, e);
+            return Json(serde_json::json!({ "success": false, "error": e.to_string() }));
+        }
     }
 
     let result = sqlx::query!(
@@ -61,38 +427,714 @@ pub async fn control_device(
     .execute(&state.pool)
     .await;
 
+    // Only a change that reached the device is logged and announced
     match result {
-        Ok(_) => Json(true),
+        Ok(done) if done.rows_affected() > 0 => {
+            if let Err(e) = events::record(&state.pool, now, &device, payload.is_on, Actor::User, "Manual control").await {
+                tracing::warn!("Failed to log control of device {}: {}", id, e);
+            }
+            live::publish(&state.events, LiveEvent::DeviceState {
+                device_id: id,
+                is_on: payload.is_on,
+                actor: Actor::User,
+                reason: "Manual control".to_string(),
+            });
+            Json(serde_json::json!({ "success": true, "expires_at": expires_at }))
+        }
+        Ok(_) => Json(serde_json::json!({ "success": false, "error": format!("Device {} not found", id) })),
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+/// Stores an override of `device_id` from `now` until `expires_at`. It replaces any that is still
+/// running for the device, in one transaction so that a failure leaves the old one in place.
+async fn record_override(pool: &sqlx::SqlitePool, device_id: i64, is_on: bool, now: NaiveDateTime, expires_at: NaiveDateTime) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM device_overrides WHERE device_id = ? AND expires_at > ?", device_id, now)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO device_overrides (device_id, is_on, created_at, expires_at) VALUES (?, ?, ?, ?)",
+        device_id,
+        is_on,
+        now,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Ends the program running on an appliance that the user takes over at `now`, and cancels runs
+/// that would start before the override expires, so that no program resumes mid-cycle.
+async fn interrupt_appliance_runs(pool: &sqlx::SqlitePool, device_id: i64, now: NaiveDateTime, expires_at: NaiveDateTime) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE appliance_runs SET end_time = ? WHERE device_id = ? AND start_time <= ? AND end_time > ?",
+        now, device_id, now, now
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM appliance_runs WHERE device_id = ? AND start_time > ? AND start_time < ?",
+        device_id, now, expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Overrides that are still in force at the current simulated time.
+pub async fn get_overrides(State(state): State<AppState>) -> Json<Vec<DeviceOverride>> {
+    let now = state.clock.snapshot().await.current_time;
+    let overrides = sqlx::query_as!(
+        DeviceOverride,
+        r#"SELECT id AS "id!", device_id, is_on, created_at, expires_at FROM device_overrides WHERE expires_at > ? ORDER BY expires_at"#,
+        now
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    Json(overrides)
+}
+
+/// Cancels an override, handing the device back to automated control on the next tick.
+pub async fn delete_override(State(state): State<AppState>, Path(id): Path<i64>) -> Json<bool> {
+    let result = sqlx::query!("DELETE FROM device_overrides WHERE id = ?", id)
+        .execute(&state.pool)
+        .await;
+
+    match result {
+        Ok(done) => Json(done.rows_affected() > 0),
         Err(_) => Json(false),
     }
 }
 
+#[derive(Deserialize)]
+pub struct EventQuery {
+    pub device_id: Option<i64>,
+    pub actor: Option<Actor>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub limit: Option<i64>, // Defaults to 100
+}
+
+/// Logged device state changes, newest first.
+pub async fn get_events(
+    State(state): State<AppState>,
+    Query(query): Query<EventQuery>,
+) -> Json<Vec<DeviceEvent>> {
+    let limit = query.limit.unwrap_or(100).max(0);
+    let events = sqlx::query_as!(
+        DeviceEvent,
+        r#"
+        SELECT id AS "id!", timestamp, device_id, device_name, old_state, new_state, actor AS "actor: Actor", reason
+        FROM device_events
+        WHERE (?1 IS NULL OR device_id = ?1)
+            AND (?2 IS NULL OR actor = ?2)
+            AND (?3 IS NULL OR timestamp >= ?3)
+            AND (?4 IS NULL OR timestamp <= ?4)
+        ORDER BY timestamp DESC, id DESC
+        LIMIT ?5
+        "#,
+        query.device_id,
+        query.actor,
+        query.from,
+        query.to,
+        limit
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    Json(events)
+}
+
+/// Server-Sent Events stream of every new energy sample, device state change and curtailment.
+pub async fn stream_events(State(state): State<AppState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(|event| {
+        // Lagged subscribers just miss the overwritten events
+        let event = event.ok()?;
+        Event::default().event(event.name()).json_data(&event).ok().map(Ok)
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Partial update of the load-shifting policy; omitted fields keep their current value.
 #[derive(Deserialize)]
 pub struct LoadShiftingControl {
-    pub enabled: bool,
+    pub enabled: Option<bool>,
+    pub peak_windows: Option<Vec<PeakWindow>>,
+    pub priority_threshold: Option<i64>,
+    pub restore: Option<RestoreStrategy>,
+    pub restore_interval_minutes: Option<i64>,
+    pub restore_import_cap_kw: Option<f64>,
 }
 
 pub async fn set_load_shifting(
     State(state): State<AppState>,
     Json(payload): Json<LoadShiftingControl>,
+) -> Json<serde_json::Value> {
+    let mut policy = state.load_shifting.lock().await;
+    let mut updated = policy.clone();
+    if let Some(enabled) = payload.enabled {
+        updated.enabled = enabled;
+    }
+    if let Some(peak_windows) = payload.peak_windows {
+        updated.peak_windows = peak_windows;
+    }
+    if let Some(priority_threshold) = payload.priority_threshold {
+        updated.priority_threshold = priority_threshold;
+    }
+    if let Some(restore) = payload.restore {
+        updated.restore = restore;
+    }
+    if let Some(interval) = payload.restore_interval_minutes {
+        updated.restore_interval_minutes = interval;
+    }
+    if let Some(cap) = payload.restore_import_cap_kw {
+        updated.restore_import_cap_kw = cap;
+    }
+    if let Err(e) = updated.validate() {
+        return Json(serde_json::json!({ "success": false, "error": e }));
+    }
+    if let Err(e) = updated.save(&state.pool).await {
+        return Json(serde_json::json!({ "success": false, "error": e.to_string() }));
+    }
+
+    *policy = updated.clone();
+    Json(serde_json::json!({ "success": true, "policy": updated }))
+}
+
+pub async fn get_load_shifting(State(state): State<AppState>) -> Json<LoadShiftingPolicy> {
+    Json(state.load_shifting.lock().await.clone())
+}
+
+pub async fn get_load_management(State(state): State<AppState>) -> Json<LoadManagement> {
+    Json(state.load_management.lock().await.clone())
+}
+
+pub async fn set_load_management(
+    State(state): State<AppState>,
+    Json(payload): Json<LoadManagement>,
+) -> Json<serde_json::Value> {
+    if let Err(e) = payload.validate() {
+        return Json(serde_json::json!({ "success": false, "error": e }));
+    }
+    *state.load_management.lock().await = payload.clone();
+    Json(serde_json::json!({ "success": true, "load_management": payload }))
+}
+
+#[derive(Deserialize)]
+pub struct CurtailmentQuery {
+    pub device_id: Option<i64>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub limit: Option<i64>, // Defaults to 100
+}
+
+/// Steps in which load management curtailed a device, newest first.
+pub async fn get_curtailments(
+    State(state): State<AppState>,
+    Query(query): Query<CurtailmentQuery>,
+) -> Json<Vec<Curtailment>> {
+    let limit = query.limit.unwrap_or(100).max(0);
+    let curtailments = sqlx::query_as!(
+        Curtailment,
+        r#"
+        SELECT id AS "id!", timestamp, device_id, device_name, requested_power, allowed_power, reason
+        FROM curtailments
+        WHERE (?1 IS NULL OR device_id = ?1)
+            AND (?2 IS NULL OR timestamp >= ?2)
+            AND (?3 IS NULL OR timestamp <= ?3)
+        ORDER BY timestamp DESC, id DESC
+        LIMIT ?4
+        "#,
+        query.device_id,
+        query.from,
+        query.to,
+        limit
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    Json(curtailments)
+}
+
+pub async fn get_ev_sessions(State(state): State<AppState>) -> Json<Vec<EvSession>> {
+    let sessions = sqlx::query_as!(
+        EvSession,
+        "SELECT id, device_id, arrival, departure, required_kwh, max_rate_kw, delivered_kwh FROM ev_sessions ORDER BY arrival DESC"
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    Json(sessions)
+}
+
+#[derive(Deserialize)]
+pub struct EvSessionInput {
+    pub device_id: i64,
+    pub arrival: Option<NaiveDateTime>, // Defaults to the current simulated time
+    pub departure: NaiveDateTime,
+    pub required_kwh: f64,
+    pub max_rate_kw: Option<f64>, // Defaults to the charger's power rating
+}
+
+pub async fn create_ev_session(
+    State(state): State<AppState>,
+    Json(payload): Json<EvSessionInput>,
+) -> Json<serde_json::Value> {
+    let device = sqlx::query_as!(
+        Device,
+        "SELECT id, name, device_type, power_rating, is_on, priority FROM devices WHERE id = ?",
+        payload.device_id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+
+    let device = match device {
+        Ok(Some(device)) if device.device_type == crate::ev::EV_CHARGER => device,
+        Ok(Some(device)) => {
+            return Json(serde_json::json!({ "success": false, "error": format!("Device {} is not an EV charger", device.id) }))
+        }
+        Ok(None) => {
+            return Json(serde_json::json!({ "success": false, "error": format!("Device {} not found", payload.device_id) }))
+        }
+        Err(e) => return Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    };
+
+    let arrival = match payload.arrival {
+        Some(arrival) => arrival,
+        None => state.clock.snapshot().await.current_time,
+    };
+    let max_rate_kw = payload.max_rate_kw.unwrap_or(device.power_rating);
+
+    if payload.departure <= arrival {
+        return Json(serde_json::json!({ "success": false, "error": "departure must be after arrival" }));
+    }
+    if payload.departure - arrival > chrono::Duration::minutes(crate::ev::MAX_SESSION_MINUTES) {
+        return Json(serde_json::json!({
+            "success": false,
+            "error": format!("departure must be within {} days of arrival", crate::ev::MAX_SESSION_MINUTES / (24 * 60))
+        }));
+    }
+    if payload.required_kwh <= 0.0 || max_rate_kw <= 0.0 {
+        return Json(serde_json::json!({ "success": false, "error": "required_kwh and max_rate_kw must be positive" }));
+    }
+
+    let result = sqlx::query!(
+        "INSERT INTO ev_sessions (device_id, arrival, departure, required_kwh, max_rate_kw) VALUES (?, ?, ?, ?, ?)",
+        device.id,
+        arrival,
+        payload.departure,
+        payload.required_kwh,
+        max_rate_kw
+    )
+    .execute(&state.pool)
+    .await;
+
+    match result {
+        Ok(done) => Json(serde_json::json!({
+            "success": true,
+            "session": EvSession {
+                id: done.last_insert_rowid(),
+                device_id: device.id,
+                arrival,
+                departure: payload.departure,
+                required_kwh: payload.required_kwh,
+                max_rate_kw,
+                delivered_kwh: 0.0,
+            }
+        })),
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+pub async fn delete_ev_session(State(state): State<AppState>, Path(id): Path<i64>) -> Json<bool> {
+    let result = sqlx::query!("DELETE FROM ev_sessions WHERE id = ?", id)
+        .execute(&state.pool)
+        .await;
+
+    match result {
+        Ok(done) => Json(done.rows_affected() > 0),
+        Err(_) => Json(false),
+    }
+}
+
+pub async fn get_appliance_programs() -> Json<&'static [Program]> {
+    Json(PROGRAMS)
+}
+
+pub async fn get_appliance_runs(State(state): State<AppState>) -> Json<Vec<ApplianceRun>> {
+    let runs = sqlx::query_as!(
+        ApplianceRun,
+        "SELECT id, device_id, program, earliest_start, finish_by, start_time, end_time FROM appliance_runs ORDER BY start_time DESC"
+    )
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    Json(runs)
+}
+
+#[derive(Deserialize)]
+pub struct ApplianceRunInput {
+    pub device_id: i64,
+    pub earliest_start: Option<NaiveDateTime>, // Defaults to the current simulated time
+    pub finish_by: NaiveDateTime,
+}
+
+/// Books a program run, starting it at the cheapest time that still finishes by the deadline.
+pub async fn create_appliance_run(
+    State(state): State<AppState>,
+    Json(payload): Json<ApplianceRunInput>,
+) -> Json<serde_json::Value> {
+    let device = sqlx::query_as!(
+        Device,
+        "SELECT id, name, device_type, power_rating, is_on, priority FROM devices WHERE id = ?",
+        payload.device_id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+
+    let (device, program) = match device {
+        Ok(Some(device)) => match appliance::program_for(&device.device_type) {
+            Some(program) => (device, program),
+            None => {
+                return Json(serde_json::json!({ "success": false, "error": format!("Device {} has no appliance program", device.id) }))
+            }
+        },
+        Ok(None) => {
+            return Json(serde_json::json!({ "success": false, "error": format!("Device {} not found", payload.device_id) }))
+        }
+        Err(e) => return Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    };
+
+    let clock = state.clock.snapshot().await;
+    let earliest_start = payload.earliest_start.unwrap_or(clock.current_time).max(clock.current_time);
+    // Each start in the window is costed, so the window is bounded
+    if payload.finish_by - earliest_start > chrono::Duration::minutes(appliance::MAX_WINDOW_MINUTES) {
+        return Json(serde_json::json!({
+            "success": false,
+            "error": format!("finish_by must be within {} days of the earliest start", appliance::MAX_WINDOW_MINUTES / (24 * 60))
+        }));
+    }
+    let tariff = TariffSchedule::load(&state.pool).await.unwrap_or_default();
+    let rate_at = |t: NaiveDateTime| tariff.rate_at(t.hour() as f64 + t.minute() as f64 / 60.0);
+
+    // An appliance runs one program at a time
+    let booked = sqlx::query!(
+        "SELECT start_time, end_time FROM appliance_runs WHERE device_id = ? AND start_time < ? AND end_time > ?",
+        device.id,
+        payload.finish_by,
+        earliest_start
+    )
+    .fetch_all(&state.pool)
+    .await;
+    let booked: Vec<(NaiveDateTime, NaiveDateTime)> = match booked {
+        Ok(rows) => rows.into_iter().map(|r| (r.start_time, r.end_time)).collect(),
+        Err(e) => return Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    };
+
+    let Some(start_time) = appliance::cheapest_start(program, device.power_rating, earliest_start, payload.finish_by, clock.step_minutes, &booked, rate_at) else {
+        return Json(serde_json::json!({
+            "success": false,
+            "error": format!("{} takes {} minutes and does not fit before {} around the programs already booked", program.name, program.duration_minutes(), payload.finish_by)
+        }));
+    };
+    let end_time = start_time + chrono::Duration::minutes(program.duration_minutes());
+
+    let result = sqlx::query!(
+        "INSERT INTO appliance_runs (device_id, program, earliest_start, finish_by, start_time, end_time) VALUES (?, ?, ?, ?, ?, ?)",
+        device.id,
+        program.name,
+        earliest_start,
+        payload.finish_by,
+        start_time,
+        end_time
+    )
+    .execute(&state.pool)
+    .await;
+
+    match result {
+        Ok(done) => Json(serde_json::json!({
+            "success": true,
+            "run": ApplianceRun {
+                id: done.last_insert_rowid(),
+                device_id: device.id,
+                program: program.name.to_string(),
+                earliest_start,
+                finish_by: payload.finish_by,
+                start_time,
+                end_time,
+            }
+        })),
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+/// Cancels a booked run. Programs that have already started cannot be interrupted.
+pub async fn delete_appliance_run(State(state): State<AppState>, Path(id): Path<i64>) -> Json<bool> {
+    let now = state.clock.snapshot().await.current_time;
+    let result = sqlx::query!("DELETE FROM appliance_runs WHERE id = ? AND start_time > ?", id, now)
+        .execute(&state.pool)
+        .await;
+
+    match result {
+        Ok(done) => Json(done.rows_affected() > 0),
+        Err(_) => Json(false),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BatteryStrategyControl {
+    pub strategy: DispatchStrategy,
+    pub shed_penalty: Option<f64>, // $/kWh, for the predictive strategy
+}
+
+pub async fn set_battery_strategy(
+    State(state): State<AppState>,
+    Json(payload): Json<BatteryStrategyControl>,
 ) -> Json<bool> {
-    let mut enabled = state.load_shifting_enabled.lock().await;
-    *enabled = payload.enabled;
+    if payload.shed_penalty.is_some_and(|penalty| penalty < 0.0 || penalty.is_nan()) {
+        return Json(false);
+    }
+    let mut dispatch = state.dispatch.lock().await;
+    dispatch.strategy = payload.strategy;
+    if let Some(penalty) = payload.shed_penalty {
+        dispatch.shed_penalty = penalty;
+    }
+    dispatch.schedule = None; // Replanned on the next tick
     Json(true)
 }
 
-pub async fn get_load_shifting(State(state): State<AppState>) -> Json<bool> {
-    let enabled = *state.load_shifting_enabled.lock().await;
-    Json(enabled)
+#[derive(Deserialize)]
+pub struct ForecastQuery {
+    pub horizon: Option<String>, // e.g. `24h` (the default), `90m` or `2d`
+}
+
+/// Household load expected from now on, learned from recent `energy_data`.
+pub async fn get_load_forecast(
+    State(state): State<AppState>,
+    Query(query): Query<ForecastQuery>,
+) -> Json<serde_json::Value> {
+    let horizon = query.horizon.as_deref().unwrap_or("24h");
+    let Some(minutes) = forecast::parse_horizon(horizon).filter(|&m| m <= MAX_HORIZON_MINUTES) else {
+        return Json(serde_json::json!({
+            "success": false,
+            "error": format!("Invalid horizon {:?}: use e.g. 24h, 90m or 2d, up to 7d", horizon)
+        }));
+    };
+
+    let now = state.clock.snapshot().await.current_time;
+    match forecast::load_forecast(&state.pool, now, minutes).await {
+        Ok(forecast) => Json(serde_json::json!({ "success": true, "forecast": forecast })),
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+/// Dispatch strategy and the plan being followed. Under the predictive strategy this is the
+/// receding-horizon plan made at the start of the current step, including load shedding.
+pub async fn get_battery_schedule(State(state): State<AppState>) -> Json<DispatchState> {
+    Json(state.dispatch.lock().await.clone())
+}
+
+pub async fn get_hvac(State(state): State<AppState>) -> Json<ThermalState> {
+    Json(state.thermal.lock().await.clone())
+}
+
+pub async fn set_hvac_comfort(
+    State(state): State<AppState>,
+    Json(payload): Json<Comfort>,
+) -> Json<serde_json::Value> {
+    if let Err(e) = payload.validate() {
+        return Json(serde_json::json!({ "success": false, "error": e }));
+    }
+    state.thermal.lock().await.comfort = payload.clone();
+    Json(serde_json::json!({ "success": true, "comfort": payload }))
+}
+
+pub async fn get_water_heater(State(state): State<AppState>) -> Json<WaterTank> {
+    Json(state.water_tank.lock().await.clone())
+}
+
+pub async fn get_clock(State(state): State<AppState>) -> Json<SimClock> {
+    Json(state.clock.snapshot().await)
+}
+
+pub async fn pause_simulation(State(state): State<AppState>) -> Json<SimClock> {
+    Json(state.clock.update(|clock| clock.paused = true).await)
+}
+
+pub async fn resume_simulation(State(state): State<AppState>) -> Json<SimClock> {
+    Json(state.clock.update(|clock| clock.paused = false).await)
+}
+
+#[derive(Deserialize)]
+pub struct SimulationSpeed {
+    pub tick_interval_ms: Option<u64>,
+    pub step_minutes: Option<i64>,
 }
 
-pub async fn generate_analysis_report(State(state): State<AppState>) -> Json<serde_json::Value> {
-    match crate::analysis::run_analysis(&state.pool).await {
-        Ok((files, summary, records)) => Json(serde_json::json!({
+pub async fn set_simulation_speed(
+    State(state): State<AppState>,
+    Json(payload): Json<SimulationSpeed>,
+) -> Json<serde_json::Value> {
+    if let Some(ms) = payload.tick_interval_ms {
+        if ms < MIN_TICK_INTERVAL_MS {
+            return Json(serde_json::json!({
+                "success": false,
+                "error": format!("tick_interval_ms must be at least {}", MIN_TICK_INTERVAL_MS)
+            }));
+        }
+    }
+    if let Some(minutes) = payload.step_minutes {
+        if !(1..=MAX_STEP_MINUTES).contains(&minutes) {
+            return Json(serde_json::json!({
+                "success": false,
+                "error": format!("step_minutes must be between 1 and {}", MAX_STEP_MINUTES)
+            }));
+        }
+    }
+
+    let clock = state.clock.update(|clock| {
+        if let Some(ms) = payload.tick_interval_ms {
+            clock.tick_interval_ms = ms;
+        }
+        if let Some(minutes) = payload.step_minutes {
+            clock.step_minutes = minutes;
+        }
+    }).await;
+    Json(serde_json::json!({ "success": true, "clock": clock }))
+}
+
+#[derive(Deserialize)]
+pub struct SimulationJump {
+    pub time: NaiveDateTime,
+}
+
+/// Moves the simulated clock so that the next sample is produced at `time`.
+pub async fn jump_simulation(
+    State(state): State<AppState>,
+    Json(payload): Json<SimulationJump>,
+) -> Json<SimClock> {
+    Json(state.clock.update(|clock| clock.current_time = payload.time).await)
+}
+
+/// Produces one sample immediately, also while paused.
+pub async fn step_simulation(State(state): State<AppState>) -> Json<SimClock> {
+    Json(state.clock.update(|clock| clock.pending_steps += 1).await)
+}
+
+/// What-if parameters for an analysis run. Omitted fields fall back to the live configuration;
+/// nothing is written to the database.
+#[derive(Deserialize, Default)]
+pub struct AnalysisRequest {
+    pub seed: Option<u64>,
+    pub start: Option<NaiveDate>, // Defaults to the simulation date
+    pub end: Option<NaiveDate>, // Inclusive; overrides `period`
+    pub period: Option<String>, // day (default), week, month or year
+    pub scenarios: Option<Vec<Scenario>>, // All of them by default
+    pub devices: Option<Vec<DeviceInput>>, // Replaces the stored devices; every one is switched on
+    pub tariff: Option<Vec<TariffInput>>, // Replaces the stored bands; must cover the whole day
+    pub pv_kwp: Option<f64>,
+    pub battery_kwh: Option<f64>,
+}
+
+/// Turns a request into run options, or the reason it cannot be run.
+async fn analysis_options(state: &AppState, request: AnalysisRequest) -> Result<AnalysisOptions, String> {
+    // Per-run seed, then the configured seed, then a fresh one. It is echoed back so the run can be repeated.
+    let seed = request.seed.or(state.config.seed).unwrap_or_else(rand::random);
+    let start = match request.start {
+        Some(start) => start,
+        None => state.clock.snapshot().await.current_time.date(),
+    };
+    let end = match request.end {
+        Some(end) => end,
+        None => crate::analysis::period_end(start, request.period.as_deref().unwrap_or("day"))
+            .ok_or("period must be one of day, week, month or year")?,
+    };
+
+    let devices = match request.devices {
+        Some(inputs) => Some(
+            inputs.into_iter()
+                .enumerate()
+                .map(|(i, input)| {
+                    let device = new_device(input).map_err(|e| format!("devices[{}]: {}", i, e))?;
+                    Ok(Device { id: i as i64 + 1, is_on: true, ..device })
+                })
+                .collect::<Result<Vec<Device>, String>>()?,
+        ),
+        None => None,
+    };
+    let tariff = match request.tariff {
+        Some(inputs) => {
+            let bands = inputs.into_iter().enumerate().map(|(i, t)| t.into_tariff(i as i64 + 1)).collect();
+            Some(TariffSchedule::from_bands(bands).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+
+    let mut solar = (*state.solar).clone();
+    if let Some(pv_kwp) = request.pv_kwp {
+        if pv_kwp < 0.0 || pv_kwp.is_nan() {
+            return Err("pv_kwp must not be negative".to_string());
+        }
+        solar.array.capacity_kwp = pv_kwp;
+    }
+    let mut battery = Battery::default();
+    if let Some(battery_kwh) = request.battery_kwh {
+        if battery_kwh <= 0.0 || battery_kwh.is_nan() {
+            return Err("battery_kwh must be positive".to_string());
+        }
+        battery.capacity_kwh = battery_kwh;
+    }
+
+    // Each run writes its CSV files to a directory of its own, so runs never overwrite each other
+    let run = format!("{}_seed{}", chrono::Utc::now().format("%Y%m%dT%H%M%S%3f"), seed);
+
+    Ok(AnalysisOptions {
+        seed,
+        start,
+        end,
+        scenarios: request.scenarios.unwrap_or_else(|| Scenario::ALL.to_vec()),
+        devices,
+        tariff,
+        battery,
+        thermal: state.thermal.lock().await.clone(),
+        policy: state.load_shifting.lock().await.clone(),
+        load_management: state.load_management.lock().await.clone(),
+        solar: Arc::new(solar),
+        reports_dir: PathBuf::from("reports").join(run),
+    })
+}
+
+/// Runs the scenario comparison for the parameters in the optional JSON body.
+pub async fn generate_analysis_report(
+    State(state): State<AppState>,
+    request: Option<Json<AnalysisRequest>>,
+) -> Json<serde_json::Value> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let options = match analysis_options(&state, request).await {
+        Ok(options) => options,
+        Err(e) => return Json(serde_json::json!({ "success": false, "error": e })),
+    };
+    let (seed, start, end) = (options.seed, options.start, options.end);
+    match crate::analysis::run_analysis(&state.pool, options).await {
+        Ok(report) => Json(serde_json::json!({
             "success": true,
-            "files": files,
-            "summary": summary,
-            "data": records
+            "seed": seed,
+            "start": start,
+            "end": end,
+            "files": report.files,
+            "summary": report.summary,
+            "data": report.records,
+            "daily": report.daily,
+            "monthly": report.monthly
         })),
         Err(e) => Json(serde_json::json!({
             "success": false,
@@ -100,3 +1142,118 @@ pub async fn generate_analysis_report(State(state): State<AppState>) -> Json<ser
         }))
     }
 }
+
+#[derive(Deserialize)]
+pub struct TariffInput {
+    pub name: String,
+    pub rate: f64,
+    pub start_hour: i64,
+    pub end_hour: i64,
+}
+
+impl TariffInput {
+    fn into_tariff(self, id: i64) -> Tariff {
+        Tariff {
+            id,
+            name: self.name,
+            rate: self.rate,
+            start_hour: self.start_hour,
+            end_hour: self.end_hour,
+        }
+    }
+}
+
+async fn fetch_tariffs(pool: &sqlx::SqlitePool) -> Result<Vec<Tariff>, sqlx::Error> {
+    sqlx::query_as!(
+        Tariff,
+        "SELECT id, name, rate, start_hour, end_hour FROM tariffs ORDER BY start_hour"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Validates `candidate` against every other stored band. Gaps are allowed while
+/// a plan is being edited; the tariff engine falls back to defaults until it is complete.
+async fn validate_tariff(pool: &sqlx::SqlitePool, candidate: &Tariff) -> Result<(), String> {
+    let mut bands: Vec<Tariff> = fetch_tariffs(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|t| t.id != candidate.id)
+        .collect();
+    bands.push(candidate.clone());
+    crate::tariff::validate(&bands, false).map_err(|e| e.to_string())
+}
+
+pub async fn get_tariffs(State(state): State<AppState>) -> Json<Vec<Tariff>> {
+    Json(fetch_tariffs(&state.pool).await.unwrap_or_default())
+}
+
+pub async fn create_tariff(
+    State(state): State<AppState>,
+    Json(payload): Json<TariffInput>,
+) -> Json<serde_json::Value> {
+    let mut tariff = payload.into_tariff(0);
+    if let Err(e) = validate_tariff(&state.pool, &tariff).await {
+        return Json(serde_json::json!({ "success": false, "error": e }));
+    }
+
+    let result = sqlx::query!(
+        "INSERT INTO tariffs (name, rate, start_hour, end_hour) VALUES (?, ?, ?, ?)",
+        tariff.name,
+        tariff.rate,
+        tariff.start_hour,
+        tariff.end_hour
+    )
+    .execute(&state.pool)
+    .await;
+
+    match result {
+        Ok(done) => {
+            tariff.id = done.last_insert_rowid();
+            Json(serde_json::json!({ "success": true, "tariff": tariff }))
+        }
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+pub async fn update_tariff(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(payload): Json<TariffInput>,
+) -> Json<serde_json::Value> {
+    let tariff = payload.into_tariff(id);
+    if let Err(e) = validate_tariff(&state.pool, &tariff).await {
+        return Json(serde_json::json!({ "success": false, "error": e }));
+    }
+
+    let result = sqlx::query!(
+        "UPDATE tariffs SET name = ?, rate = ?, start_hour = ?, end_hour = ? WHERE id = ?",
+        tariff.name,
+        tariff.rate,
+        tariff.start_hour,
+        tariff.end_hour,
+        id
+    )
+    .execute(&state.pool)
+    .await;
+
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            Json(serde_json::json!({ "success": false, "error": format!("Tariff {} not found", id) }))
+        }
+        Ok(_) => Json(serde_json::json!({ "success": true, "tariff": tariff })),
+        Err(e) => Json(serde_json::json!({ "success": false, "error": e.to_string() })),
+    }
+}
+
+pub async fn delete_tariff(State(state): State<AppState>, Path(id): Path<i64>) -> Json<bool> {
+    let result = sqlx::query!("DELETE FROM tariffs WHERE id = ?", id)
+        .execute(&state.pool)
+        .await;
+
+    match result {
+        Ok(done) => Json(done.rows_affected() > 0),
+        Err(_) => Json(false),
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{Duration, NaiveDateTime};
+use serde::Serialize;
+
+/// One stage of an appliance program.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct Phase {
+    pub name: &'static str,
+    pub minutes: i64,
+    pub load: f64, // Fraction of the device's power rating drawn during the phase
+}
+
+/// Fixed cycle an appliance runs from start to finish once started.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct Program {
+    pub device_type: &'static str,
+    pub name: &'static str,
+    pub phases: &'static [Phase],
+}
+
+/// Longest window (minutes) between the earliest start and the deadline of a booking
+pub const MAX_WINDOW_MINUTES: i64 = 7 * 24 * 60;
+
+pub const PROGRAMS: &[Program] = &[
+    Program {
+        device_type: "washing_machine",
+        name: "Cotton 40°",
+        phases: &[
+            Phase { name: "heat", minutes: 30, load: 1.0 },
+            Phase { name: "wash", minutes: 45, load: 0.2 },
+            Phase { name: "rinse", minutes: 30, load: 0.15 },
+            Phase { name: "spin", minutes: 15, load: 0.5 },
+        ],
+    },
+    Program {
+        device_type: "dishwasher",
+        name: "Eco",
+        phases: &[
+            Phase { name: "prewash", minutes: 15, load: 0.15 },
+            Phase { name: "heat", minutes: 30, load: 1.0 },
+            Phase { name: "wash", minutes: 30, load: 0.15 },
+            Phase { name: "hot rinse", minutes: 15, load: 1.0 },
+            Phase { name: "dry", minutes: 30, load: 0.05 },
+        ],
+    },
+    Program {
+        device_type: "tumble_dryer",
+        name: "Cupboard dry",
+        phases: &[
+            Phase { name: "dry", minutes: 75, load: 1.0 },
+            Phase { name: "cool down", minutes: 15, load: 0.1 },
+        ],
+    },
+];
+
+pub fn program_for(device_type: &str) -> Option<&'static Program> {
+    PROGRAMS.iter().find(|p| p.device_type == device_type)
+}
+
+impl Program {
+    pub fn duration_minutes(&self) -> i64 {
+        self.phases.iter().map(|p| p.minutes).sum()
+    }
+
+    /// Mean power (kW) over `minutes` starting `offset` minutes into the run, for a device rated `rating` kW.
+    /// Time before the start or after the end of the program counts as zero.
+    pub fn mean_power(&self, rating: f64, offset: i64, minutes: i64) -> f64 {
+        if minutes <= 0 {
+            return 0.0;
+        }
+        let (window_start, window_end) = (offset, offset + minutes);
+        let mut phase_start = 0;
+        let mut energy = 0.0; // kW·min
+        for phase in self.phases {
+            let phase_end = phase_start + phase.minutes;
+            let overlap = phase_end.min(window_end) - phase_start.max(window_start);
+            if overlap > 0 {
+                energy += overlap as f64 * phase.load * rating;
+            }
+            phase_start = phase_end;
+        }
+        energy / minutes as f64
+    }
+
+    /// Cost of running the program from `start` on a grid of `step_minutes` steps priced by `rate_at`.
+    fn cost_from(&self, rating: f64, start: NaiveDateTime, step_minutes: i64, rate_at: &impl Fn(NaiveDateTime) -> f64) -> f64 {
+        let steps = (self.duration_minutes() + step_minutes - 1) / step_minutes;
+        (0..steps)
+            .map(|k| {
+                let power = self.mean_power(rating, k * step_minutes, step_minutes);
+                power * step_minutes as f64 / 60.0 * rate_at(start + Duration::minutes(k * step_minutes))
+            })
+            .sum()
+    }
+}
+
+/// Cheapest start, on the step grid beginning at `earliest`, that still finishes by `finish_by`
+/// and does not overlap any of the `booked` (start, end) runs. Ties go to the earliest start.
+/// `None` if the program does not fit in the window.
+pub fn cheapest_start(
+    program: &Program,
+    rating: f64,
+    earliest: NaiveDateTime,
+    finish_by: NaiveDateTime,
+    step_minutes: i64,
+    booked: &[(NaiveDateTime, NaiveDateTime)],
+    rate_at: impl Fn(NaiveDateTime) -> f64,
+) -> Option<NaiveDateTime> {
+    let duration = Duration::minutes(program.duration_minutes());
+    let latest = finish_by - duration;
+    let mut best: Option<(f64, NaiveDateTime)> = None;
+    let mut start = earliest;
+    while start <= latest {
+        if booked.iter().any(|&(from, to)| from < start + duration && to > start) {
+            start += Duration::minutes(step_minutes);
+            continue;
+        }
+        let cost = program.cost_from(rating, start, step_minutes, &rate_at);
+        if best.is_none_or(|(lowest, _)| cost < lowest - 1e-12) {
+            best = Some((cost, start));
+        }
+        start += Duration::minutes(step_minutes);
+    }
+    best.map(|(_, start)| start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, Timelike};
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_mean_power_follows_phases() {
+        let program = program_for("washing_machine").unwrap();
+        assert_eq!(program.duration_minutes(), 120);
+        assert!((program.mean_power(1.5, 0, 30) - 1.5).abs() < 1e-9);
+        assert!((program.mean_power(1.5, -30, 30)).abs() < 1e-9);
+
+        // Splitting the run into steps preserves its energy
+        let total: f64 = (0..5).map(|k| program.mean_power(1.5, k * 30, 30) * 0.5).sum();
+        let expected: f64 = program.phases.iter().map(|p| p.minutes as f64 / 60.0 * p.load * 1.5).sum();
+        assert!((total - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_picks_cheapest_start_before_deadline() {
+        // Evening start with a 07:00 deadline: 0.30 until 21:00, 0.15 until midnight, 0.10 overnight
+        let rate = |t: NaiveDateTime| match t.hour() {
+            17..=20 => 0.30,
+            0..=5 => 0.10,
+            _ => 0.15,
+        };
+        let program = program_for("dishwasher").unwrap();
+        let start = cheapest_start(program, 1.2, at(18), at(18) + Duration::hours(13), 30, &[], rate).unwrap();
+        assert_eq!(start, at(0) + Duration::days(1));
+
+        // A run already booked overnight pushes it to the next cheapest free start
+        let booked = [(at(0) + Duration::days(1), at(6) + Duration::days(1))];
+        let start = cheapest_start(program, 1.2, at(18), at(18) + Duration::hours(13), 30, &booked, rate).unwrap();
+        assert_eq!(start, at(21));
+
+        // No room for a two-hour program in a one-hour window
+        assert_eq!(cheapest_start(program, 1.2, at(18), at(19), 30, &[], rate), None);
+    }
+}
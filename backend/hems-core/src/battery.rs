@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+/// Stationary home battery with a simple energy-balance model.
+///
+/// State of charge is tracked in percent of usable capacity and is
+/// integrated over every simulation step. Losses are split evenly between
+/// charging and discharging so that a full cycle matches the round-trip
+/// efficiency.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Battery {
+    pub capacity_kwh: f64,
+    pub soc: f64, // State of Charge %
+    pub min_soc: f64, // %
+    pub max_soc: f64, // %
+    pub round_trip_efficiency: f64, // 0.0 - 1.0
+    pub max_charge_kw: f64,
+    pub max_discharge_kw: f64,
+}
+
+impl Default for Battery {
+    fn default() -> Self {
+        Self {
+            capacity_kwh: 10.0,
+            soc: 50.0,
+            min_soc: 10.0,
+            max_soc: 95.0,
+            round_trip_efficiency: 0.90,
+            max_charge_kw: 3.0,
+            max_discharge_kw: 3.0,
+        }
+    }
+}
+
+impl Battery {
+    fn one_way_efficiency(&self) -> f64 {
+        self.round_trip_efficiency.clamp(0.0, 1.0).sqrt()
+    }
+
+    /// Energy (kWh) currently stored above the minimum SOC.
+    pub fn available_energy(&self) -> f64 {
+        ((self.soc - self.min_soc).max(0.0) / 100.0) * self.capacity_kwh
+    }
+
+    /// Energy (kWh) that can still be stored before reaching the maximum SOC.
+    pub fn headroom(&self) -> f64 {
+        ((self.max_soc - self.soc).max(0.0) / 100.0) * self.capacity_kwh
+    }
+
+    /// Largest charging power (kW) the battery accepts for a step of `hours`.
+    pub fn max_charge_power(&self, hours: f64) -> f64 {
+        if hours <= 0.0 {
+            return 0.0;
+        }
+        let eta = self.one_way_efficiency();
+        if eta <= 0.0 {
+            return 0.0;
+        }
+        self.max_charge_kw.min(self.headroom() / (hours * eta))
+    }
+
+    /// Largest discharging power (kW) the battery can deliver for a step of `hours`.
+    pub fn max_discharge_power(&self, hours: f64) -> f64 {
+        if hours <= 0.0 {
+            return 0.0;
+        }
+        self.max_discharge_kw.min(self.available_energy() * self.one_way_efficiency() / hours)
+    }
+
+    /// Charges with up to `power_kw` for `hours` and returns the power actually absorbed.
+    pub fn charge(&mut self, power_kw: f64, hours: f64) -> f64 {
+        let power = power_kw.max(0.0).min(self.max_charge_power(hours));
+        let stored = power * hours * self.one_way_efficiency();
+        self.soc = (self.soc + stored / self.capacity_kwh * 100.0).min(self.max_soc);
+        power
+    }
+
+    /// Discharges up to `power_kw` for `hours` and returns the power actually delivered.
+    pub fn discharge(&mut self, power_kw: f64, hours: f64) -> f64 {
+        let power = power_kw.max(0.0).min(self.max_discharge_power(hours));
+        let eta = self.one_way_efficiency();
+        if eta > 0.0 {
+            let drawn = power * hours / eta;
+            self.soc = (self.soc - drawn / self.capacity_kwh * 100.0).max(self.min_soc);
+        }
+        power
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soc_stays_within_limits() {
+        let mut battery = Battery::default();
+        for _ in 0..20 {
+            battery.charge(5.0, 0.5);
+        }
+        assert!((battery.soc - battery.max_soc).abs() < 1e-9);
+        assert_eq!(battery.charge(3.0, 0.5), 0.0);
+
+        for _ in 0..20 {
+            battery.discharge(5.0, 0.5);
+        }
+        assert!((battery.soc - battery.min_soc).abs() < 1e-9);
+        assert_eq!(battery.discharge(3.0, 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_round_trip_losses() {
+        // Floor the battery at its starting SOC so only the charged energy can come back out
+        let mut battery = Battery { soc: 50.0, min_soc: 50.0, ..Battery::default() };
+        let charged = battery.charge(1.0, 0.5) * 0.5;
+        let delivered = battery.discharge(3.0, 0.5) * 0.5;
+
+        assert!((delivered / charged - battery.round_trip_efficiency).abs() < 1e-9);
+        assert!((battery.soc - 50.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+/// Known appliance type with the defaults used when a device is created without them.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct DeviceType {
+    pub device_type: &'static str,
+    pub label: &'static str,
+    pub default_power_rating: f64, // kW
+    pub default_priority: i64, // Higher number = higher priority
+}
+
+pub const DEVICE_TYPES: &[DeviceType] = &[
+    DeviceType { device_type: "washing_machine", label: "Washing Machine", default_power_rating: 1.5, default_priority: 1 },
+    DeviceType { device_type: "dishwasher", label: "Dishwasher", default_power_rating: 1.2, default_priority: 1 },
+    DeviceType { device_type: "tumble_dryer", label: "Tumble Dryer", default_power_rating: 2.5, default_priority: 1 },
+    DeviceType { device_type: "pool_pump", label: "Pool Pump", default_power_rating: 1.1, default_priority: 0 },
+    DeviceType { device_type: "ev_charger", label: "EV Charger", default_power_rating: 7.0, default_priority: 2 },
+    DeviceType { device_type: "water_heater", label: "Water Heater", default_power_rating: 3.0, default_priority: 1 },
+    DeviceType { device_type: "hvac", label: "HVAC", default_power_rating: 3.0, default_priority: 3 },
+    DeviceType { device_type: "refrigerator", label: "Refrigerator", default_power_rating: 0.15, default_priority: 4 },
+    DeviceType { device_type: "lighting", label: "Lighting", default_power_rating: 0.3, default_priority: 3 },
+];
+
+pub fn lookup(device_type: &str) -> Option<&'static DeviceType> {
+    DEVICE_TYPES.iter().find(|t| t.device_type == device_type)
+}
@@ -0,0 +1,58 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+
+pub const DEFAULT_TICK_INTERVAL_MS: u64 = 2000;
+pub const DEFAULT_STEP_MINUTES: i64 = 30;
+
+pub const MIN_TICK_INTERVAL_MS: u64 = 100;
+pub const MAX_STEP_MINUTES: i64 = 24 * 60;
+
+/// Simulated clock driving the `Simulator`. Every real `tick_interval_ms` the
+/// simulator produces a sample at `current_time` and advances it by `step_minutes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SimClock {
+    pub current_time: NaiveDateTime, // Time of the next sample
+    pub paused: bool,
+    pub tick_interval_ms: u64,
+    pub step_minutes: i64,
+    #[serde(skip)]
+    pub pending_steps: u32, // Single steps requested through the API
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self {
+            current_time: Utc::now().naive_utc(),
+            paused: false,
+            tick_interval_ms: DEFAULT_TICK_INTERVAL_MS,
+            step_minutes: DEFAULT_STEP_MINUTES,
+            pending_steps: 0,
+        }
+    }
+}
+
+/// Shared clock plus a notifier that wakes the simulator whenever the clock is changed.
+#[derive(Clone, Default)]
+pub struct ClockHandle {
+    pub clock: Arc<Mutex<SimClock>>,
+    pub changed: Arc<Notify>,
+}
+
+impl ClockHandle {
+    pub async fn snapshot(&self) -> SimClock {
+        self.clock.lock().await.clone()
+    }
+
+    /// Applies `f` to the clock and wakes the simulator so the change takes effect immediately.
+    pub async fn update<F: FnOnce(&mut SimClock)>(&self, f: F) -> SimClock {
+        let snapshot = {
+            let mut clock = self.clock.lock().await;
+            f(&mut clock);
+            clock.clone()
+        };
+        self.changed.notify_one();
+        snapshot
+    }
+}
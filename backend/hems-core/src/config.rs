@@ -0,0 +1,46 @@
+use std::str::FromStr;
+use crate::solar::PvArray;
+
+/// Runtime settings read from the environment (or `.env`).
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Seed for every random draw in the simulator and analysis (`HEMS_SEED`).
+    /// Unset means a fresh random seed per run.
+    pub seed: Option<u64>,
+    /// Credit ($/kWh) for energy exported to the grid (`HEMS_EXPORT_RATE`)
+    pub export_rate: f64,
+    /// PV array (`HEMS_PV_KWP`, `HEMS_PV_TILT`, `HEMS_PV_AZIMUTH`, `HEMS_LATITUDE`)
+    pub pv: PvArray,
+    /// Hourly irradiance/cloud-cover CSV driving solar generation (`HEMS_WEATHER_CSV`).
+    /// Unset means clear sky every day.
+    pub weather_csv: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let default_pv = PvArray::default();
+        Self {
+            seed: parse_env("HEMS_SEED"),
+            export_rate: parse_env("HEMS_EXPORT_RATE").unwrap_or(0.0),
+            pv: PvArray {
+                capacity_kwp: parse_env("HEMS_PV_KWP").unwrap_or(default_pv.capacity_kwp),
+                tilt_deg: parse_env("HEMS_PV_TILT").unwrap_or(default_pv.tilt_deg),
+                azimuth_deg: parse_env("HEMS_PV_AZIMUTH").unwrap_or(default_pv.azimuth_deg),
+                latitude_deg: parse_env("HEMS_LATITUDE").unwrap_or(default_pv.latitude_deg),
+                ..default_pv
+            },
+            weather_csv: std::env::var("HEMS_WEATHER_CSV").ok(),
+        }
+    }
+}
+
+fn parse_env<T: FromStr>(key: &str) -> Option<T> {
+    let value = std::env::var(key).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            tracing::warn!("Ignoring invalid {}={:?}", key, value);
+            None
+        }
+    }
+}
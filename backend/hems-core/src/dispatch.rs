@@ -0,0 +1,326 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::battery::Battery;
+
+// Coarsest SOC grid resolution for the dynamic program
+const ENERGY_RESOLUTION_KWH: f64 = 0.05;
+/// How far (SOC %) the battery may drift from the schedule before it is planned again
+pub const SOC_DRIFT_PERCENT: f64 = 2.0;
+/// Default cost ($/kWh) the predictive controller assigns to flexible load it does not serve
+pub const DEFAULT_SHED_PENALTY: f64 = 0.25;
+
+/// How the simulator decides battery charge/discharge.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DispatchStrategy {
+    /// Charge on solar surplus, discharge on deficit
+    #[default]
+    Greedy,
+    /// Follow a cost-minimizing schedule computed from the tariff and forecasts
+    Optimal,
+    /// Model-predictive control: every step, re-plan the battery and the shedding of flexible
+    /// load over the next 24 hours and apply only the first step. Replaces rule-based peak shaving.
+    Predictive,
+}
+
+/// Expected conditions for one step of the planning horizon.
+#[derive(Debug, Clone, Copy)]
+pub struct ForecastStep {
+    pub rate: f64, // $/kWh import price
+    pub solar: f64, // kW
+    pub load: f64, // kW that has to be served
+    pub flexible: f64, // kW of low-priority load that may be shed instead
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedStep {
+    pub time: NaiveDateTime,
+    pub battery_power: f64, // kW, positive = charge, negative = discharge
+    pub shed: bool, // Flexible load switched off for the step
+    pub soc: f64, // % at the end of the step
+    pub grid_import: f64, // kW
+    pub grid_export: f64, // kW
+    pub cost: f64, // $
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatterySchedule {
+    pub start: NaiveDateTime,
+    pub step_minutes: i64,
+    pub start_soc: f64, // % when the schedule was planned
+    pub total_cost: f64,
+    pub steps: Vec<PlannedStep>,
+}
+
+impl BatterySchedule {
+    /// Planned step starting at `time`, if the schedule covers it.
+    pub fn step_at(&self, time: NaiveDateTime) -> Option<&PlannedStep> {
+        let elapsed = (time - self.start).num_minutes();
+        if elapsed < 0 || elapsed % self.step_minutes != 0 {
+            return None;
+        }
+        self.steps.get((elapsed / self.step_minutes) as usize)
+    }
+
+    /// Planned battery power (kW) for the step starting at `time`, if the schedule covers it.
+    pub fn setpoint_at(&self, time: NaiveDateTime) -> Option<f64> {
+        self.step_at(time).map(|s| s.battery_power)
+    }
+
+    /// SOC (%) the schedule expects at the start of the step starting at `time`.
+    pub fn soc_at(&self, time: NaiveDateTime) -> Option<f64> {
+        let elapsed = (time - self.start).num_minutes();
+        if elapsed < 0 || elapsed % self.step_minutes != 0 {
+            return None;
+        }
+        match (elapsed / self.step_minutes) as usize {
+            0 => Some(self.start_soc),
+            step => self.steps.get(step - 1).map(|s| s.soc),
+        }
+    }
+}
+
+/// Shared strategy selection and the schedule currently being followed.
+#[derive(Debug, Clone, Serialize)]
+pub struct DispatchState {
+    pub strategy: DispatchStrategy,
+    /// $/kWh of flexible load left unserved, weighed against import cost by the predictive strategy
+    pub shed_penalty: f64,
+    pub schedule: Option<BatterySchedule>,
+}
+
+impl Default for DispatchState {
+    fn default() -> Self {
+        Self { strategy: DispatchStrategy::default(), shed_penalty: DEFAULT_SHED_PENALTY, schedule: None }
+    }
+}
+
+/// Computes a cost-minimizing battery schedule by dynamic programming over a discretized SOC.
+///
+/// Each step may charge (from solar or the grid) or discharge within the battery's power
+/// and SOC limits. Exported energy earns `export_rate`. Energy left in the battery at the
+/// end of the horizon is valued at the cheapest import rate, so the plan does not simply
+/// empty the battery on the last step. A step's flexible load is shed whenever deferring it
+/// is cheaper than serving it: shed energy is made up later, in the step where extra load
+/// costs least, and shedding costs `shed_penalty` on top. The last step has no later step
+/// and never sheds.
+pub fn optimize(
+    battery: &Battery,
+    forecast: &[ForecastStep],
+    start: NaiveDateTime,
+    step_minutes: i64,
+    export_rate: f64,
+    shed_penalty: f64,
+) -> BatterySchedule {
+    let hours = step_minutes as f64 / 60.0;
+    let eta = battery.round_trip_efficiency.clamp(0.0, 1.0).sqrt();
+
+    let min_energy = battery.min_soc / 100.0 * battery.capacity_kwh;
+    let max_energy = battery.max_soc / 100.0 * battery.capacity_kwh;
+    // Largest change in stored energy per step. Short steps get a finer grid so that the
+    // battery can still move at least one level per step.
+    let up_energy = battery.max_charge_kw * hours * eta;
+    let down_energy = if eta > 0.0 { battery.max_discharge_kw * hours / eta } else { 0.0 };
+    let resolution = [up_energy, down_energy].into_iter()
+        .filter(|&energy| energy > 0.0)
+        .fold(ENERGY_RESOLUTION_KWH, f64::min);
+    let levels = ((max_energy - min_energy) / resolution).floor().max(0.0) as usize + 1;
+    let energy_of = |level: usize| min_energy + level as f64 * resolution;
+    let max_up = (up_energy / resolution + 1e-9).floor() as usize;
+    let max_down = (down_energy / resolution + 1e-9).floor() as usize;
+
+    // AC battery power for moving from one level to another
+    let power_for = |from: usize, to: usize| -> f64 {
+        let delta = (to as f64 - from as f64) * resolution;
+        if delta >= 0.0 {
+            if eta > 0.0 { delta / (eta * hours) } else { 0.0 }
+        } else {
+            delta * eta / hours
+        }
+    };
+
+    // Price ($/kWh) of extra load in each step before the battery: lost export under a solar
+    // surplus, the import rate otherwise. Load shed at step t is made up in `rebound[t]`, the
+    // cheapest later step.
+    let marginal: Vec<f64> = forecast.iter()
+        .map(|step| if step.load + step.flexible < step.solar { export_rate } else { step.rate })
+        .collect();
+    let rebound: Vec<Option<usize>> = (0..forecast.len())
+        .map(|t| (t + 1..forecast.len()).min_by(|&a, &b| marginal[a].total_cmp(&marginal[b])))
+        .collect();
+
+    // Grid import, export and cost of a step, with `extra` kW of earlier deferred load on top
+    let serve = |t: usize, power: f64, shed: bool, extra: f64| -> (f64, f64, f64) {
+        let step = &forecast[t];
+        let load = if shed { step.load } else { step.load + step.flexible };
+        let net = load + extra + power - step.solar;
+        let grid_import = net.max(0.0);
+        let grid_export = (-net).max(0.0);
+        let penalty = if shed { step.flexible * shed_penalty } else { 0.0 };
+        (grid_import, grid_export, (grid_import * step.rate - grid_export * export_rate + penalty) * hours)
+    };
+    // Cheaper of serving and deferring the flexible load, counting what making it up later
+    // costs: (shed, cost)
+    let step_cost = |t: usize, power: f64| -> (bool, f64) {
+        let (_, _, cost) = serve(t, power, false, 0.0);
+        if let Some(later) = rebound[t].filter(|_| forecast[t].flexible > 0.0) {
+            let (_, _, shed_cost) = serve(t, power, true, 0.0);
+            let shed_cost = shed_cost + forecast[t].flexible * marginal[later] * hours;
+            if shed_cost < cost {
+                return (true, shed_cost);
+            }
+        }
+        (false, cost)
+    };
+
+    // Backward pass: value[level] = minimum cost-to-go from this level
+    let min_rate = forecast.iter().map(|s| s.rate).fold(f64::MAX, f64::min);
+    let terminal_value = if forecast.is_empty() { 0.0 } else { min_rate };
+    let mut value: Vec<f64> = (0..levels).map(|l| -(energy_of(l) - min_energy) * eta * terminal_value).collect();
+    let mut choice: Vec<Vec<usize>> = vec![vec![0; levels]; forecast.len()];
+
+    for t in (0..forecast.len()).rev() {
+        let mut next_value = vec![f64::MAX; levels];
+        for from in 0..levels {
+            let lowest = from.saturating_sub(max_down);
+            let highest = (from + max_up).min(levels - 1);
+            for (to, future) in value.iter().enumerate().take(highest + 1).skip(lowest) {
+                let (_, cost) = step_cost(t, power_for(from, to));
+                let total = cost + future;
+                if total < next_value[from] {
+                    next_value[from] = total;
+                    choice[t][from] = to;
+                }
+            }
+        }
+        value = next_value;
+    }
+
+    // Forward pass from the level closest to the current SOC
+    let current_energy = (battery.soc / 100.0 * battery.capacity_kwh).clamp(min_energy, max_energy);
+    let mut level = (((current_energy - min_energy) / resolution).round() as usize).min(levels - 1);
+    let mut steps = Vec::with_capacity(forecast.len());
+    let mut total_cost = 0.0;
+    let mut deferred = vec![0.0; forecast.len()]; // kWh to make up in each step
+
+    for t in 0..forecast.len() {
+        let next = choice[t][level];
+        let battery_power = power_for(level, next);
+        let (shed, _) = step_cost(t, battery_power);
+        if let Some(later) = rebound[t].filter(|_| shed) {
+            deferred[later] += forecast[t].flexible * hours;
+        }
+        let (grid_import, grid_export, cost) = serve(t, battery_power, shed, deferred[t] / hours);
+        total_cost += cost;
+        steps.push(PlannedStep {
+            time: start + chrono::Duration::minutes(step_minutes * t as i64),
+            battery_power,
+            shed,
+            soc: energy_of(next) / battery.capacity_kwh * 100.0,
+            grid_import,
+            grid_export,
+            cost,
+        });
+        level = next;
+    }
+
+    BatterySchedule { start, step_minutes, start_soc: battery.soc, total_cost, steps }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn midnight() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_charges_off_peak_for_peak() {
+        // Cheap first half, expensive second half, constant 2 kW load and no solar
+        let forecast: Vec<ForecastStep> = (0..8)
+            .map(|t| ForecastStep { rate: if t < 4 { 0.10 } else { 0.40 }, solar: 0.0, load: 2.0, flexible: 0.0 })
+            .collect();
+        let battery = Battery { soc: 10.0, ..Battery::default() };
+        let schedule = optimize(&battery, &forecast, midnight(), 30, 0.0, DEFAULT_SHED_PENALTY);
+
+        assert!(schedule.steps[..4].iter().any(|s| s.battery_power > 0.0), "should charge from the grid off-peak");
+        assert!(schedule.steps[4..].iter().any(|s| s.battery_power < 0.0), "should discharge at peak");
+
+        let idle_cost: f64 = forecast.iter().map(|s| s.load * s.rate * 0.5).sum();
+        assert!(schedule.total_cost < idle_cost);
+    }
+
+    #[test]
+    fn test_respects_battery_limits() {
+        let forecast: Vec<ForecastStep> = (0..48)
+            .map(|t| ForecastStep { rate: if t % 2 == 0 { 0.05 } else { 0.50 }, solar: 1.0, load: 0.5, flexible: 0.0 })
+            .collect();
+        let battery = Battery::default();
+        let schedule = optimize(&battery, &forecast, midnight(), 30, 0.0, DEFAULT_SHED_PENALTY);
+
+        assert_eq!(schedule.steps.len(), 48);
+        for step in &schedule.steps {
+            assert!(step.battery_power <= battery.max_charge_kw + 1e-9);
+            assert!(-step.battery_power <= battery.max_discharge_kw + 1e-9);
+            assert!(step.soc >= battery.min_soc - 1e-9 && step.soc <= battery.max_soc + 1e-9);
+        }
+        assert_eq!(schedule.setpoint_at(midnight()), Some(schedule.steps[0].battery_power));
+        assert_eq!(schedule.setpoint_at(midnight() + chrono::Duration::hours(24)), None);
+        assert_eq!(schedule.soc_at(midnight()), Some(battery.soc));
+        assert_eq!(schedule.soc_at(midnight() + chrono::Duration::minutes(30)), Some(schedule.steps[0].soc));
+    }
+
+    #[test]
+    fn test_short_steps_still_move_the_battery() {
+        // One-minute steps move less than the default grid resolution per step
+        let forecast: Vec<ForecastStep> = (0..120)
+            .map(|t| ForecastStep { rate: if t < 60 { 0.10 } else { 0.40 }, solar: 0.0, load: 2.0, flexible: 0.0 })
+            .collect();
+        let battery = Battery { soc: 10.0, ..Battery::default() };
+        let schedule = optimize(&battery, &forecast, midnight(), 1, 0.0, DEFAULT_SHED_PENALTY);
+
+        assert!(schedule.steps[..60].iter().any(|s| s.battery_power > 0.0));
+        assert!(schedule.steps[60..].iter().any(|s| s.battery_power < 0.0));
+        for step in &schedule.steps {
+            assert!(step.battery_power <= battery.max_charge_kw + 1e-9);
+            assert!(-step.battery_power <= battery.max_discharge_kw + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_sheds_flexible_load_when_cheaper() {
+        // 1 kW of flexible load: cheap overnight, peak after that and solar at the end
+        let rates = [0.10, 0.10, 0.40, 0.40, 0.15, 0.15];
+        let forecast: Vec<ForecastStep> = rates.iter().enumerate()
+            .map(|(t, &rate)| ForecastStep { rate, solar: if t >= 4 { 2.0 } else { 0.0 }, load: 0.5, flexible: 1.0 })
+            .collect();
+        let shed = |schedule: &BatterySchedule| schedule.steps.iter().map(|s| s.shed).collect::<Vec<_>>();
+
+        // Without a usable battery only the peak is worth going without
+        let no_battery = Battery { max_charge_kw: 0.0, max_discharge_kw: 0.0, ..Battery::default() };
+        let schedule = optimize(&no_battery, &forecast, midnight(), 30, 0.0, 0.25);
+        assert_eq!(shed(&schedule), vec![false, false, true, true, false, false]);
+        // The 1 kWh shed at peak is made up with the first solar surplus instead of being dropped
+        assert!((schedule.steps[4].grid_import - 1.5).abs() < 1e-9);
+        assert!(schedule.steps[5].grid_import.abs() < 1e-9);
+
+        // A battery charged overnight covers the peak more cheaply than shedding
+        let battery = Battery { soc: 10.0, ..Battery::default() };
+        let schedule = optimize(&battery, &forecast, midnight(), 30, 0.0, 0.25);
+        assert!(shed(&schedule).iter().all(|&s| !s));
+        assert!(schedule.steps[2..4].iter().all(|s| s.battery_power < 0.0));
+
+        // Load the household values above the peak price is always served
+        let schedule = optimize(&no_battery, &forecast, midnight(), 30, 0.0, 0.50);
+        assert!(shed(&schedule).iter().all(|&s| !s));
+
+        // Nothing is shed when making the load up later costs as much as serving it now
+        let flat: Vec<ForecastStep> = (0..6)
+            .map(|_| ForecastStep { rate: 0.40, solar: 0.0, load: 0.5, flexible: 1.0 })
+            .collect();
+        let schedule = optimize(&no_battery, &flat, midnight(), 30, 0.0, 0.0);
+        assert!(shed(&schedule).iter().all(|&s| !s));
+    }
+}
@@ -0,0 +1,110 @@
+use serde::Deserialize;
+use crate::models::Device;
+
+pub const EV_CHARGER: &str = "ev_charger";
+/// Longest charging session (minutes); every step until departure is planned each tick
+pub const MAX_SESSION_MINUTES: i64 = 7 * 24 * 60;
+
+/// Conditions for one step in which an EV may charge.
+#[derive(Debug, Clone, Copy)]
+pub struct ChargeSlot {
+    pub rate: f64, // $/kWh import price
+    pub solar_surplus: f64, // kW of solar left after the rest of the house
+}
+
+/// Charging power (kW) for each slot that delivers `required_kwh` at the lowest cost.
+///
+/// Solar surplus is valued at `solar_value` (what exporting it would have earned) and grid
+/// energy at the slot's tariff rate. Because every slot is independent and linear, filling
+/// the cheapest capacity first is optimal; ties go to the earliest slot. If the slots cannot
+/// hold the full requirement the EV simply charges as much as it can.
+pub fn schedule_charging(
+    slots: &[ChargeSlot],
+    required_kwh: f64,
+    max_rate_kw: f64,
+    step_hours: f64,
+    solar_value: f64,
+) -> Vec<f64> {
+    let mut power = vec![0.0; slots.len()];
+    if required_kwh <= 0.0 || max_rate_kw <= 0.0 || step_hours <= 0.0 {
+        return power;
+    }
+
+    // (price, slot, capacity kW) for the solar and grid portion of every slot
+    let mut chunks: Vec<(f64, usize, f64)> = Vec::with_capacity(slots.len() * 2);
+    for (i, slot) in slots.iter().enumerate() {
+        let solar = slot.solar_surplus.clamp(0.0, max_rate_kw);
+        if solar > 0.0 {
+            chunks.push((solar_value, i, solar));
+        }
+        chunks.push((slot.rate, i, max_rate_kw - solar));
+    }
+    chunks.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+    let mut remaining = required_kwh;
+    for (_, i, capacity) in chunks {
+        if remaining <= 1e-9 {
+            break;
+        }
+        let kw = capacity.min(remaining / step_hours);
+        power[i] += kw;
+        remaining -= kw * step_hours;
+    }
+
+    power
+}
+
+/// An EV charging need within the single day simulated by the analysis.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvDemand {
+    pub device_id: i64,
+    pub arrival_step: usize, // First half-hour step the car is plugged in
+    pub departure_step: usize, // Step by which charging must be finished (exclusive)
+    pub required_kwh: f64,
+    pub max_rate_kw: f64,
+}
+
+impl EvDemand {
+    /// Typical commuter: plugs in at 18:00 needing 14 kWh before midnight.
+    pub fn evening_commute(device: &Device) -> Self {
+        Self {
+            device_id: device.id,
+            arrival_step: 36,
+            departure_step: 48,
+            required_kwh: 14.0,
+            max_rate_kw: device.power_rating,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fills_cheapest_slots_first() {
+        let slots: Vec<ChargeSlot> = [0.30, 0.30, 0.15, 0.10, 0.10, 0.15]
+            .iter()
+            .map(|&rate| ChargeSlot { rate, solar_surplus: 0.0 })
+            .collect();
+        let power = schedule_charging(&slots, 10.0, 7.0, 0.5, 0.0);
+
+        assert_eq!(power[..2], [0.0, 0.0]);
+        assert_eq!(power[3], 7.0);
+        assert_eq!(power[4], 7.0);
+        assert!((power[2] - 6.0).abs() < 1e-9); // Earliest of the 0.15 slots takes the remaining 3 kWh
+        assert_eq!(power[5], 0.0);
+        let delivered: f64 = power.iter().map(|p| p * 0.5).sum();
+        assert!((delivered - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_prefers_solar_surplus() {
+        let slots = vec![
+            ChargeSlot { rate: 0.10, solar_surplus: 0.0 },
+            ChargeSlot { rate: 0.15, solar_surplus: 3.0 },
+        ];
+        let power = schedule_charging(&slots, 1.5, 7.0, 0.5, 0.0);
+        assert_eq!(power, vec![0.0, 3.0]);
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::NaiveDateTime;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use crate::models::{Actor, Device};
+
+/// Appends a state change of `device` (whose `is_on` is still the old state) to the event log.
+pub async fn record(
+    pool: &SqlitePool,
+    timestamp: NaiveDateTime,
+    device: &Device,
+    new_state: bool,
+    actor: Actor,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO device_events (timestamp, device_id, device_name, old_state, new_state, actor, reason)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+        timestamp,
+        device.id,
+        device.name,
+        device.is_on,
+        new_state,
+        actor,
+        reason
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Devices whose most recent state change was being switched off by the load shifter. Each
+/// device's latest event is found through the `(device_id, id)` index, so this stays cheap as
+/// the log grows.
+pub async fn shed_devices(pool: &SqlitePool) -> Result<HashSet<i64>, sqlx::Error> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        SELECT e.device_id
+        FROM devices d
+        JOIN device_events e ON e.id = (SELECT MAX(id) FROM device_events WHERE device_id = d.id)
+        WHERE e.actor = 'load_shifter' AND e.new_state = 0
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids.into_iter().collect())
+}
+
+/// Devices whose most recent state change was being switched off by load management.
+pub async fn curtailed_devices(pool: &SqlitePool) -> Result<HashSet<i64>, sqlx::Error> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        SELECT e.device_id
+        FROM devices d
+        JOIN device_events e ON e.id = (SELECT MAX(id) FROM device_events WHERE device_id = d.id)
+        WHERE e.actor = 'load_manager' AND e.new_state = 0
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids.into_iter().collect())
+}
+
+/// Time the load shifter last switched a device back on at or before `now`, if ever. Restores
+/// after `now` are ignored, as they are left over from before the clock was turned back.
+pub async fn last_restore(pool: &SqlitePool, now: NaiveDateTime) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT MAX(timestamp) AS "timestamp: NaiveDateTime" FROM device_events WHERE actor = 'load_shifter' AND new_state = 1 AND timestamp <= ?"#,
+        now
+    )
+    .fetch_one(pool)
+    .await
+}
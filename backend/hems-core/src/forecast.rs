@@ -0,0 +1,194 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike, Weekday};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap};
+
+/// Forecast resolution; the profile has one slot per half hour of the day
+pub const SLOT_MINUTES: i64 = 30;
+/// How far back `energy_data` is read when learning the profile
+pub const HISTORY_DAYS: i64 = 28;
+/// Longest forecast served
+pub const MAX_HORIZON_MINUTES: i64 = 7 * 24 * 60;
+// Weight of the newest sample in the exponential smoothing
+const SMOOTHING: f64 = 0.3;
+
+fn slot_of(time: NaiveDateTime) -> u32 {
+    (time.hour() * 60 + time.minute()) / SLOT_MINUTES as u32
+}
+
+/// Start of the slot containing `time`.
+pub fn slot_start(time: NaiveDateTime) -> NaiveDateTime {
+    let minute = time.minute() / SLOT_MINUTES as u32 * SLOT_MINUTES as u32;
+    time.date().and_hms_opt(time.hour(), minute, 0).unwrap_or(time)
+}
+
+/// Typical household consumption per weekday and half hour, learned from history.
+#[derive(Debug, Clone, Default)]
+pub struct LoadProfile {
+    weekday: HashMap<(Weekday, u32), f64>,
+    daily: HashMap<u32, f64>, // Same slot on any day, for weekdays without history
+    mean: Option<f64>,
+    pub samples: usize,
+}
+
+impl LoadProfile {
+    /// Learns from (timestamp, kW) samples. The samples are averaged per day and slot first,
+    /// then each slot is exponentially smoothed across days so recent weeks count more than
+    /// older ones, however finely the history was sampled.
+    pub fn learn(history: &[(NaiveDateTime, f64)]) -> Self {
+        let smooth = |previous: Option<&f64>, value: f64| match previous {
+            Some(previous) => SMOOTHING * value + (1.0 - SMOOTHING) * previous,
+            None => value,
+        };
+
+        let mut days: BTreeMap<(NaiveDate, u32), (f64, usize)> = BTreeMap::new();
+        for &(time, load) in history {
+            let (total, count) = days.entry((time.date(), slot_of(time))).or_default();
+            *total += load;
+            *count += 1;
+        }
+
+        let mut profile = LoadProfile { samples: history.len(), ..LoadProfile::default() };
+        for (&(date, slot), &(total, count)) in &days {
+            let load = total / count as f64;
+            let key = (date.weekday(), slot);
+            profile.weekday.insert(key, smooth(profile.weekday.get(&key), load));
+            profile.daily.insert(slot, smooth(profile.daily.get(&slot), load));
+        }
+        if !history.is_empty() {
+            profile.mean = Some(history.iter().map(|(_, load)| load).sum::<f64>() / history.len() as f64);
+        }
+        profile
+    }
+
+    /// Expected load (kW) at `time`, falling back from the weekday slot to the same slot on any
+    /// day and then to the overall mean. `None` without any history.
+    pub fn predict(&self, time: NaiveDateTime) -> Option<f64> {
+        let slot = slot_of(time);
+        self.weekday.get(&(time.weekday(), slot))
+            .or_else(|| self.daily.get(&slot))
+            .copied()
+            .or(self.mean)
+    }
+}
+
+/// Parses a horizon such as `24h`, `90m` or `2d`; a bare number is hours. Returns minutes.
+pub fn parse_horizon(horizon: &str) -> Option<i64> {
+    let horizon = horizon.trim();
+    let (value, unit_minutes) = match horizon.char_indices().last()? {
+        (i, 'm') => (&horizon[..i], 1),
+        (i, 'h') => (&horizon[..i], 60),
+        (i, 'd') => (&horizon[..i], 24 * 60),
+        _ => (horizon, 60),
+    };
+    let minutes = value.trim().parse::<i64>().ok()?.checked_mul(unit_minutes)?;
+    (minutes > 0).then_some(minutes)
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ForecastPoint {
+    pub timestamp: NaiveDateTime,
+    pub load: f64, // kW
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LoadForecast {
+    pub generated_at: NaiveDateTime,
+    pub step_minutes: i64,
+    pub history_samples: usize, // Samples the profile was learned from
+    pub points: Vec<ForecastPoint>,
+}
+
+/// Learns the load profile from the `HISTORY_DAYS` of `energy_data` before `now`.
+pub async fn learn_profile(pool: &SqlitePool, now: NaiveDateTime) -> Result<LoadProfile, sqlx::Error> {
+    let since = now - chrono::Duration::days(HISTORY_DAYS);
+    let rows = sqlx::query!(
+        r#"
+        SELECT timestamp, home_consumption
+        FROM energy_data
+        WHERE timestamp >= ? AND timestamp < ?
+        ORDER BY timestamp
+        "#,
+        since,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let history: Vec<(NaiveDateTime, f64)> = rows.into_iter().map(|r| (r.timestamp, r.home_consumption)).collect();
+    Ok(LoadProfile::learn(&history))
+}
+
+/// Half-hourly load forecast over `horizon_minutes` from the slot containing `now`.
+/// Slots the profile cannot predict are left out.
+pub async fn load_forecast(pool: &SqlitePool, now: NaiveDateTime, horizon_minutes: i64) -> Result<LoadForecast, sqlx::Error> {
+    let profile = learn_profile(pool, now).await?;
+    let start = slot_start(now);
+    let points = (0..(horizon_minutes + SLOT_MINUTES - 1) / SLOT_MINUTES)
+        .filter_map(|k| {
+            let timestamp = start + chrono::Duration::minutes(k * SLOT_MINUTES);
+            profile.predict(timestamp).map(|load| ForecastPoint { timestamp, load })
+        })
+        .collect();
+
+    Ok(LoadForecast { generated_at: now, step_minutes: SLOT_MINUTES, history_samples: profile.samples, points })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_learns_weekday_profile() {
+        // Two weeks of 1 kW, except 3 kW on Monday evenings (2024-01-01 is a Monday)
+        let history: Vec<(NaiveDateTime, f64)> = (1..=14)
+            .flat_map(|day| (0..24).map(move |hour| (at(day, hour), if day % 7 == 1 && hour == 18 { 3.0 } else { 1.0 })))
+            .collect();
+        let profile = LoadProfile::learn(&history);
+
+        assert!((profile.predict(at(15, 18)).unwrap() - 3.0).abs() < 1e-9);
+        assert!((profile.predict(at(16, 18)).unwrap() - 1.0).abs() < 1e-9);
+        // No samples at :30 for Monday's weekday slot; the profile falls back to the mean
+        let half_past = at(15, 18) + chrono::Duration::minutes(30);
+        assert!((profile.predict(half_past).unwrap() - (14.0 * 24.0 + 4.0) / (14.0 * 24.0)).abs() < 1e-9);
+
+        // Newer samples outweigh older ones
+        let changing = LoadProfile::learn(&[(at(1, 12), 1.0), (at(8, 12), 3.0)]);
+        assert!((changing.predict(at(15, 12)).unwrap() - 1.6).abs() < 1e-9);
+        // Another weekday uses the same slot on any day
+        assert!((changing.predict(at(16, 12)).unwrap() - 1.6).abs() < 1e-9);
+
+        assert_eq!(LoadProfile::learn(&[]).predict(at(1, 12)), None);
+    }
+
+    #[test]
+    fn test_smooths_slot_averages_across_weeks() {
+        // Two Mondays sampled every 10 minutes: 1 kW throughout the first, 3 kW in the second.
+        // Smoothing the three samples of each slot one by one would weight the second Monday
+        // at 1 - 0.7^3 = 0.657 instead of 0.3.
+        let history: Vec<(NaiveDateTime, f64)> = [(1, 1.0), (8, 3.0)].into_iter()
+            .flat_map(|(day, load)| (0..3).map(move |k| (at(day, 12) + chrono::Duration::minutes(10 * k), load)))
+            .collect();
+        let profile = LoadProfile::learn(&history);
+        assert_eq!(profile.samples, 6);
+        assert!((profile.predict(at(15, 12)).unwrap() - 1.6).abs() < 1e-9);
+
+        // Samples within a slot are averaged before smoothing
+        let uneven = LoadProfile::learn(&[(at(1, 12), 1.0), (at(1, 12) + chrono::Duration::minutes(10), 2.0), (at(1, 12) + chrono::Duration::minutes(20), 3.0)]);
+        assert!((uneven.predict(at(8, 12)).unwrap() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_horizon() {
+        assert_eq!(parse_horizon("24h"), Some(24 * 60));
+        assert_eq!(parse_horizon("90m"), Some(90));
+        assert_eq!(parse_horizon("2d"), Some(2 * 24 * 60));
+        assert_eq!(parse_horizon("6"), Some(6 * 60));
+        assert_eq!(parse_horizon("0h"), None);
+        assert_eq!(parse_horizon("soon"), None);
+    }
+}
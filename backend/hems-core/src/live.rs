@@ -0,0 +1,40 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+use crate::models::{Actor, Curtailment, EnergyData};
+
+// Slow subscribers that fall further behind than this skip the missed events
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Event published to live subscribers as soon as it happens.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveEvent {
+    Energy(EnergyData),
+    DeviceState {
+        device_id: i64,
+        is_on: bool,
+        actor: Actor,
+        reason: String,
+    },
+    Curtailment(Curtailment),
+}
+
+impl LiveEvent {
+    /// SSE event name, so clients can subscribe with `addEventListener`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LiveEvent::Energy(_) => "energy",
+            LiveEvent::DeviceState { .. } => "device_state",
+            LiveEvent::Curtailment(_) => "curtailment",
+        }
+    }
+}
+
+pub fn channel() -> broadcast::Sender<LiveEvent> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+/// Publishes an event. Having no subscribers is not an error.
+pub fn publish(events: &broadcast::Sender<LiveEvent>, event: LiveEvent) {
+    let _ = events.send(event);
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+/// Limit on the household's grid connection and how load is curtailed to respect it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadManagement {
+    pub enabled: bool,
+    /// kW the household may import at once (main breaker / connection capacity)
+    pub max_import_kw: f64,
+    /// kW below which a modulating load (EV charger, HVAC, water heater) cannot run and is stopped instead
+    pub min_modulation_kw: f64,
+}
+
+impl Default for LoadManagement {
+    fn default() -> Self {
+        Self { enabled: true, max_import_kw: 10.0, min_modulation_kw: 1.4 }
+    }
+}
+
+/// One load competing for the connection in a step.
+#[derive(Debug, Clone, Copy)]
+pub struct Load {
+    pub priority: i64,
+    pub power: f64, // kW it would draw without a limit
+    /// Can draw any power between `min_modulation_kw` and `power`, rather than only all or nothing
+    pub modulating: bool,
+    /// User overrides and running appliance programs; only curtailed once nothing else is left
+    pub protected: bool,
+}
+
+impl LoadManagement {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_import_kw <= 0.0 || self.max_import_kw.is_nan() {
+            return Err("max_import_kw must be positive".to_string());
+        }
+        if self.min_modulation_kw < 0.0 || self.min_modulation_kw.is_nan() {
+            return Err("min_modulation_kw must not be negative".to_string());
+        }
+        Ok(())
+    }
+
+    /// kW of extra import still allowed on top of `import_kw`; unlimited when disabled.
+    pub fn headroom(&self, import_kw: f64) -> f64 {
+        if self.enabled { (self.max_import_kw - import_kw).max(0.0) } else { f64::INFINITY }
+    }
+
+    /// Power (kW) each of `loads` may draw so that `fixed_kw` (uncontrollable load net of
+    /// solar) plus the loads stays within `max_import_kw`. Lowest priority loads are curtailed
+    /// first, modulating ones before switched ones at equal priority. If the fixed load alone
+    /// exceeds the limit every load is stopped.
+    pub fn allocate(&self, loads: &[Load], fixed_kw: f64) -> Vec<f64> {
+        let mut allowed: Vec<f64> = loads.iter().map(|l| l.power).collect();
+        if !self.enabled {
+            return allowed;
+        }
+
+        let mut excess = fixed_kw + allowed.iter().sum::<f64>() - self.max_import_kw;
+        let mut order: Vec<usize> = (0..loads.len()).collect();
+        order.sort_by_key(|&i| (loads[i].protected, loads[i].priority, !loads[i].modulating));
+
+        for i in order {
+            if excess <= 1e-9 {
+                break;
+            }
+            let load = &loads[i];
+            let reduced = load.power - excess;
+            let cut = if load.modulating && reduced >= self.min_modulation_kw { excess } else { load.power };
+            allowed[i] -= cut;
+            excess -= cut;
+        }
+
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(priority: i64, power: f64, modulating: bool) -> Load {
+        Load { priority, power, modulating, protected: false }
+    }
+
+    #[test]
+    fn test_curtails_lowest_priority_first() {
+        let limits = LoadManagement { max_import_kw: 10.0, ..LoadManagement::default() };
+        // Washer (priority 1), EV (priority 2, modulating) and HVAC (priority 3)
+        let loads = [load(1, 1.5, false), load(2, 7.0, true), load(3, 3.0, true)];
+
+        // 11.5 kW: switching the washer off is enough
+        assert_eq!(limits.allocate(&loads, 0.0), vec![0.0, 7.0, 3.0]);
+
+        // 13.7 kW: the washer goes and the EV charges at what is left
+        let allowed = limits.allocate(&loads, 2.2);
+        assert_eq!(allowed[0], 0.0);
+        assert!((allowed[1] - 4.8).abs() < 1e-9);
+        assert_eq!(allowed[2], 3.0);
+
+        // Below its minimum rate the EV stops altogether
+        let allowed = limits.allocate(&loads, 6.0);
+        assert_eq!(allowed[..2], [0.0, 0.0]);
+        assert!((allowed[2] - 3.0).abs() < 1e-9);
+
+        // Protected loads keep running while anything else can give way
+        let protected = [Load { protected: true, ..loads[0] }, loads[1], loads[2]];
+        let allowed = limits.allocate(&protected, 0.0);
+        assert_eq!(allowed[0], 1.5);
+        assert!((allowed[1] - 5.5).abs() < 1e-9);
+
+        // Disabled: nothing is curtailed
+        let disabled = LoadManagement { enabled: false, ..limits };
+        assert_eq!(disabled.allocate(&loads, 6.0), vec![1.5, 7.0, 3.0]);
+    }
+}
@@ -0,0 +1,205 @@
+use chrono::{Datelike, NaiveDateTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use crate::appliance;
+use crate::models::Device;
+use crate::tariff::TariffSchedule;
+
+/// Longest a shed device waits for room under the restore cap before the staggered strategy
+/// switches it back on anyway
+pub const MAX_RESTORE_DELAY_MINUTES: i64 = 120;
+
+/// Hours of the day during which low-priority load is shed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeakWindow {
+    pub start_hour: f64,
+    pub end_hour: f64, // Before `start_hour` for a window that crosses midnight
+    /// Days the window applies to; empty means every day
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+}
+
+impl PeakWindow {
+    fn contains(&self, weekday: Weekday, hour: f64) -> bool {
+        let on_day = self.weekdays.is_empty() || self.weekdays.contains(&weekday);
+        let in_hours = if self.start_hour < self.end_hour {
+            hour >= self.start_hour && hour < self.end_hour
+        } else {
+            hour >= self.start_hour || hour < self.end_hour
+        };
+        on_day && in_hours
+    }
+}
+
+/// What happens to shed devices once the peak is over.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreStrategy {
+    /// Switch every shed device back on at the first off-peak step
+    #[default]
+    Immediate,
+    /// Leave shed devices off until the user turns them back on
+    Manual,
+    /// Switch shed devices back on one at a time, highest priority first, while import stays under the cap
+    Staggered,
+}
+
+/// Load-shifting rules shared by the live simulator and the offline analysis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadShiftingPolicy {
+    pub enabled: bool,
+    /// Explicit peak windows; when empty the tariff's peak bands are used
+    pub peak_windows: Vec<PeakWindow>,
+    /// Devices with a priority below this are shed during peak
+    pub priority_threshold: i64,
+    pub restore: RestoreStrategy,
+    /// Minimum time between two staggered restores
+    pub restore_interval_minutes: i64,
+    /// kW of grid import a staggered restore may not push the household above
+    pub restore_import_cap_kw: f64,
+}
+
+impl Default for LoadShiftingPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            peak_windows: Vec::new(),
+            priority_threshold: 2,
+            restore: RestoreStrategy::Immediate,
+            restore_interval_minutes: 30,
+            restore_import_cap_kw: 5.0,
+        }
+    }
+}
+
+impl LoadShiftingPolicy {
+    /// Loads the saved policy, or the default if none has been saved or it can no longer be read.
+    pub async fn load(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let stored = sqlx::query_scalar!("SELECT policy FROM load_shifting_policy WHERE id = 1")
+            .fetch_optional(pool)
+            .await?;
+        let Some(stored) = stored else {
+            return Ok(Self::default());
+        };
+        match serde_json::from_str(&stored) {
+            Ok(policy) => Ok(policy),
+            Err(e) => {
+                tracing::warn!("Saved load-shifting policy is unreadable, using default: {}", e);
+                Ok(Self::default())
+            }
+        }
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let policy = serde_json::to_string(self).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+        sqlx::query!(
+            "INSERT INTO load_shifting_policy (id, policy) VALUES (1, ?) ON CONFLICT (id) DO UPDATE SET policy = excluded.policy",
+            policy
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for window in &self.peak_windows {
+            let valid_hour = |h: f64| (0.0..=24.0).contains(&h);
+            if !valid_hour(window.start_hour) || !valid_hour(window.end_hour) || window.start_hour == window.end_hour {
+                return Err(format!(
+                    "Invalid peak window {}-{}: hours must be within 0-24 and differ",
+                    window.start_hour, window.end_hour
+                ));
+            }
+        }
+        if self.restore_interval_minutes < 0 {
+            return Err("restore_interval_minutes must not be negative".to_string());
+        }
+        if self.restore_import_cap_kw <= 0.0 || self.restore_import_cap_kw.is_nan() {
+            return Err("restore_import_cap_kw must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether `time` falls in a peak window, regardless of whether shifting is enabled.
+    pub fn is_peak(&self, tariff: &TariffSchedule, time: NaiveDateTime) -> bool {
+        let hour = time.hour() as f64 + time.minute() as f64 / 60.0;
+        if self.peak_windows.is_empty() {
+            return tariff.is_peak(hour);
+        }
+        self.peak_windows.iter().any(|w| w.contains(time.weekday(), hour))
+    }
+
+    /// Whether the load shifter may switch `device` off during peak. Appliances that run programs
+    /// are left alone: their booked runs are shifted instead, and they are switched by the program.
+    pub fn sheds(&self, device: &Device) -> bool {
+        self.enabled && device.priority < self.priority_threshold && appliance::program_for(&device.device_type).is_none()
+    }
+
+    /// Shed device to switch back on next under the staggered strategy, given the household's
+    /// current grid import (kW): the highest priority one (smallest first among equals) that
+    /// fits under the import cap. If none fits and the devices have waited
+    /// `MAX_RESTORE_DELAY_MINUTES` (`overdue`), the first of them comes back regardless so that
+    /// nothing stays off indefinitely. Otherwise `None`.
+    pub fn next_restore<'a>(&self, waiting: &[&'a Device], import_kw: f64, overdue: bool) -> Option<&'a Device> {
+        let mut candidates = waiting.to_vec();
+        candidates.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.power_rating.total_cmp(&b.power_rating)));
+        candidates.iter()
+            .find(|d| import_kw + d.power_rating <= self.restore_import_cap_kw)
+            .or(candidates.first().filter(|_| overdue))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_weekday_windows() {
+        let policy = LoadShiftingPolicy {
+            peak_windows: vec![
+                PeakWindow { start_hour: 17.0, end_hour: 21.0, weekdays: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri] },
+                PeakWindow { start_hour: 22.0, end_hour: 1.0, weekdays: vec![Weekday::Sat] },
+            ],
+            ..LoadShiftingPolicy::default()
+        };
+        let tariff = TariffSchedule::default();
+        let at = |day: u32, hour: u32| NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(hour, 0, 0).unwrap();
+
+        // 2024-01-01 is a Monday, 2024-01-06 a Saturday
+        assert!(policy.is_peak(&tariff, at(1, 18)));
+        assert!(!policy.is_peak(&tariff, at(6, 18)));
+        assert!(policy.is_peak(&tariff, at(6, 23)));
+        assert!(!policy.is_peak(&tariff, at(1, 23)));
+
+        // Without explicit windows the tariff decides
+        let tariff_driven = LoadShiftingPolicy::default();
+        assert!(tariff_driven.is_peak(&tariff, at(6, 18)));
+        assert!(!tariff_driven.is_peak(&tariff, at(6, 12)));
+    }
+
+    #[test]
+    fn test_next_restore_respects_cap() {
+        let device = |id: i64, power_rating: f64, priority: i64| Device {
+            id, name: format!("Device {}", id), device_type: "test".to_string(), power_rating, is_on: false, priority,
+        };
+        let (small, large, urgent) = (device(1, 0.5, 0), device(2, 2.0, 0), device(3, 3.0, 1));
+        let waiting = [&small, &large, &urgent];
+        let policy = LoadShiftingPolicy { restore_import_cap_kw: 4.0, ..LoadShiftingPolicy::default() };
+
+        // Highest priority goes first while it fits, otherwise the smallest device that does
+        assert_eq!(policy.next_restore(&waiting, 0.5, false).map(|d| d.id), Some(3));
+        assert_eq!(policy.next_restore(&waiting, 1.5, false).map(|d| d.id), Some(1));
+        assert_eq!(policy.next_restore(&waiting, 3.8, false).map(|d| d.id), None);
+        // After waiting too long the highest priority device comes back even above the cap
+        assert_eq!(policy.next_restore(&waiting, 3.8, true).map(|d| d.id), Some(3));
+        let oversized = device(4, 6.0, 0);
+        assert_eq!(policy.next_restore(&[&oversized], 0.0, false).map(|d| d.id), None);
+        assert_eq!(policy.next_restore(&[&oversized], 0.0, true).map(|d| d.id), Some(4));
+
+        // Appliances follow their programs and are never shed
+        assert!(policy.sheds(&small));
+        assert!(!policy.sheds(&Device { device_type: "washing_machine".to_string(), ..small.clone() }));
+    }
+}
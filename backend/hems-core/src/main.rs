@@ -2,6 +2,24 @@ mod models;
 mod simulation;
 mod api;
 mod analysis;
+mod battery;
+mod tariff;
+mod catalogue;
+mod telemetry;
+mod live;
+mod clock;
+mod config;
+mod dispatch;
+mod ev;
+mod thermal;
+mod appliance;
+mod water_heater;
+mod events;
+mod overrides;
+mod load_shifting;
+mod load_management;
+mod solar;
+mod forecast;
 
 use axum::{
     routing::get,
@@ -13,18 +31,23 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use tower_http::cors::{Any, CorsLayer};
 use std::str::FromStr;
 
-use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use std::time::Instant;
+use tokio::sync::{broadcast, Mutex};
 
 use sqlx::SqlitePool;
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: SqlitePool,
-    pub user_overrides: Arc<Mutex<HashMap<i64, Instant>>>,
-    pub load_shifting_enabled: Arc<Mutex<bool>>,
+    pub load_shifting: Arc<Mutex<load_shifting::LoadShiftingPolicy>>,
+    pub load_management: Arc<Mutex<load_management::LoadManagement>>,
+    pub events: broadcast::Sender<live::LiveEvent>,
+    pub clock: clock::ClockHandle,
+    pub config: Arc<config::Config>,
+    pub dispatch: Arc<Mutex<dispatch::DispatchState>>,
+    pub thermal: Arc<Mutex<thermal::ThermalState>>,
+    pub water_tank: Arc<Mutex<water_heater::WaterTank>>,
+    pub solar: Arc<solar::SolarModel>,
 }
 
 #[tokio::main]
@@ -39,6 +62,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenvy::dotenv().ok();
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let config = Arc::new(config::Config::from_env());
 
     // Connect to database
     let connection_options = SqliteConnectOptions::from_str(&database_url)?
@@ -56,17 +80,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing::info!("Migrations ran successfully");
 
+    // Weather for the PV model; without it every day is clear
+    let weather = config.weather_csv.as_ref().and_then(|path| match solar::WeatherData::load(path) {
+        Ok(weather) => {
+            tracing::info!("Loaded {} hours of weather from {}", weather.len(), path);
+            Some(weather)
+        }
+        Err(e) => {
+            tracing::warn!("Could not load weather from {}, assuming clear sky: {}", path, e);
+            None
+        }
+    });
+    let solar = Arc::new(solar::SolarModel { array: config.pv.clone(), weather });
+
+    let load_shifting = load_shifting::LoadShiftingPolicy::load(&pool).await?;
+
     // Initialize AppState
-    let user_overrides = Arc::new(Mutex::new(HashMap::new()));
-    let load_shifting_enabled = Arc::new(Mutex::new(true)); // Default to enabled
     let app_state = AppState {
         pool: pool.clone(),
-        user_overrides: user_overrides.clone(),
-        load_shifting_enabled: load_shifting_enabled.clone(),
+        load_shifting: Arc::new(Mutex::new(load_shifting)),
+        load_management: Arc::new(Mutex::new(load_management::LoadManagement::default())),
+        events: live::channel(),
+        clock: clock::ClockHandle::default(),
+        config,
+        dispatch: Arc::new(Mutex::new(dispatch::DispatchState::default())),
+        thermal: Arc::new(Mutex::new(thermal::ThermalState::default())),
+        water_tank: Arc::new(Mutex::new(water_heater::WaterTank::default())),
+        solar,
     };
 
     // Start Simulation
-    let simulator = simulation::Simulator::new(pool.clone(), user_overrides.clone(), load_shifting_enabled.clone());
+    let simulator = simulation::Simulator::new(&app_state);
     tokio::spawn(async move {
         simulator.start().await;
     });
@@ -81,9 +125,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let app = Router::new()
         .route("/", get(root))
         .route("/api/energy", get(api::get_latest_energy))
-        .route("/api/devices", get(api::get_devices))
+        .route("/api/energy/history", get(api::get_energy_history))
+        .route("/api/stream", get(api::stream_events))
+        .route("/api/events", get(api::get_events))
+        .route("/api/devices", get(api::get_devices).post(api::create_device))
+        .route("/api/devices/types", get(api::get_device_types))
+        .route("/api/devices/{id}", axum::routing::put(api::update_device).delete(api::delete_device))
+        .route("/api/devices/breakdown", get(api::get_device_breakdown))
+        .route("/api/devices/{id}/history", get(api::get_device_history))
         .route("/api/devices/{id}/control", axum::routing::post(api::control_device))
+        .route("/api/overrides", get(api::get_overrides))
+        .route("/api/overrides/{id}", axum::routing::delete(api::delete_override))
         .route("/api/control/load-shifting", axum::routing::post(api::set_load_shifting).get(api::get_load_shifting))
+        .route("/api/control/load-management", get(api::get_load_management).put(api::set_load_management))
+        .route("/api/curtailments", get(api::get_curtailments))
+        .route("/api/tariffs", get(api::get_tariffs).post(api::create_tariff))
+        .route("/api/tariffs/{id}", axum::routing::put(api::update_tariff).delete(api::delete_tariff))
+        .route("/api/simulation/clock", get(api::get_clock))
+        .route("/api/simulation/pause", axum::routing::post(api::pause_simulation))
+        .route("/api/simulation/resume", axum::routing::post(api::resume_simulation))
+        .route("/api/simulation/speed", axum::routing::post(api::set_simulation_speed))
+        .route("/api/simulation/jump", axum::routing::post(api::jump_simulation))
+        .route("/api/simulation/step", axum::routing::post(api::step_simulation))
+        .route("/api/ev/sessions", get(api::get_ev_sessions).post(api::create_ev_session))
+        .route("/api/ev/sessions/{id}", axum::routing::delete(api::delete_ev_session))
+        .route("/api/appliances/programs", get(api::get_appliance_programs))
+        .route("/api/appliances/runs", get(api::get_appliance_runs).post(api::create_appliance_run))
+        .route("/api/appliances/runs/{id}", axum::routing::delete(api::delete_appliance_run))
+        .route("/api/forecast/load", get(api::get_load_forecast))
+        .route("/api/battery/schedule", get(api::get_battery_schedule))
+        .route("/api/hvac", get(api::get_hvac))
+        .route("/api/hvac/comfort", axum::routing::put(api::set_hvac_comfort))
+        .route("/api/water-heater", get(api::get_water_heater))
+        .route("/api/control/battery-strategy", axum::routing::post(api::set_battery_strategy))
         .route("/api/analysis/generate", axum::routing::post(api::generate_analysis_report))
         .layer(cors)
         .with_state(app_state);
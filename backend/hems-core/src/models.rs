@@ -13,6 +13,7 @@ pub struct EnergyData {
     pub battery_discharge: f64, // kW
     pub home_consumption: f64, // kW
     pub battery_soc: f64, // State of Charge %
+    pub cost: f64, // $ for this interval's grid import
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
@@ -25,3 +26,92 @@ pub struct Device {
     pub priority: i64, // Higher number = higher priority
 }
 
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Tariff {
+    pub id: i64,
+    pub name: String,
+    pub rate: f64, // $/kWh
+    pub start_hour: i64, // inclusive, 0-23
+    pub end_hour: i64, // exclusive, 1-24
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct EvSession {
+    pub id: i64,
+    pub device_id: i64,
+    pub arrival: NaiveDateTime, // Plugged in
+    pub departure: NaiveDateTime, // Charging deadline
+    pub required_kwh: f64,
+    pub max_rate_kw: f64,
+    pub delivered_kwh: f64,
+}
+
+/// What a device drew during one simulator step.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct DeviceReading {
+    pub id: i64,
+    pub device_id: i64,
+    pub timestamp: NaiveDateTime,
+    pub power: f64, // kW
+    pub energy: f64, // kWh
+}
+
+/// A program run booked for an appliance. Once started it runs to `end_time` uninterrupted.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct ApplianceRun {
+    pub id: i64,
+    pub device_id: i64,
+    pub program: String,
+    pub earliest_start: NaiveDateTime,
+    pub finish_by: NaiveDateTime, // Deadline given by the user
+    pub start_time: NaiveDateTime, // Chosen by the scheduler
+    pub end_time: NaiveDateTime,
+}
+
+/// A manual device state that automated control must respect until `expires_at` (simulated time).
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct DeviceOverride {
+    pub id: i64,
+    pub device_id: i64,
+    pub is_on: bool,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Who initiated a device state change
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Actor {
+    User,
+    LoadShifter,
+    Scheduler,
+    LoadManager,
+}
+
+/// A recorded device state change, stamped with simulated time.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct DeviceEvent {
+    pub id: i64,
+    pub timestamp: NaiveDateTime,
+    pub device_id: i64,
+    pub device_name: String, // Kept so the entry stays readable after the device is deleted
+    pub old_state: bool,
+    pub new_state: bool,
+    pub actor: Actor,
+    pub reason: String,
+}
+
+/// A step in which load management held a device below the power it wanted, to stay
+/// within the household import limit.
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct Curtailment {
+    pub id: i64,
+    pub timestamp: NaiveDateTime,
+    pub device_id: i64,
+    pub device_name: String,
+    pub requested_power: f64, // kW
+    pub allowed_power: f64, // kW
+    pub reason: String,
+}
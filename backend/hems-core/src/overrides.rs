@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+/// How long a manual change holds when no expiry is given (simulated minutes)
+pub const DEFAULT_OVERRIDE_MINUTES: i64 = 6 * 60;
+
+/// Devices under a user override at `now`, with the state the user chose. Automated controllers
+/// leave these alone, and the simulator holds them in that state.
+pub async fn active(pool: &SqlitePool, now: NaiveDateTime) -> Result<HashMap<i64, bool>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT device_id, is_on FROM device_overrides WHERE created_at <= ? AND expires_at > ?",
+        now,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| (r.device_id, r.is_on)).collect())
+}
@@ -1,51 +1,425 @@
 use sqlx::SqlitePool;
 use tokio::time::{Duration};
-use chrono::{Utc, Timelike, NaiveDateTime};
-use rand::Rng;
+use chrono::{Datelike, Timelike, NaiveDateTime};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use crate::models::Device;
+use tokio::sync::{broadcast, Mutex};
+use crate::models::{Actor, ApplianceRun, Curtailment, Device, EnergyData, EvSession};
+use crate::live::{self, LiveEvent};
+use crate::clock::ClockHandle;
+use crate::battery::Battery;
+use crate::tariff::TariffSchedule;
+use crate::dispatch::{self, DispatchState, DispatchStrategy, ForecastStep};
+use crate::ev::{self, ChargeSlot, EV_CHARGER};
+use crate::thermal::{HvacMode, ThermalState, HVAC};
+use crate::appliance;
+use crate::events;
+use crate::overrides;
+use crate::load_shifting::{LoadShiftingPolicy, RestoreStrategy, MAX_RESTORE_DELAY_MINUTES};
+use crate::load_management::{Load, LoadManagement};
+use crate::solar::SolarModel;
+use crate::forecast::{self, LoadProfile};
+use crate::water_heater::{self, HeatingStrategy, WaterTank, WATER_HEATER};
+use crate::AppState;
 
-use std::collections::HashMap;
-use std::time::Instant;
+use std::collections::{HashMap, HashSet};
 
 pub struct Simulator {
     pool: SqlitePool,
-    current_time: Arc<Mutex<NaiveDateTime>>,
-    user_overrides: Arc<Mutex<HashMap<i64, Instant>>>,
-    load_shifting_enabled: Arc<Mutex<bool>>,
+    clock: ClockHandle,
+    battery: Mutex<Battery>,
+    rng: Mutex<StdRng>,
+    load_shifting: Arc<Mutex<LoadShiftingPolicy>>,
+    load_management: Arc<Mutex<LoadManagement>>,
+    events: broadcast::Sender<LiveEvent>,
+    dispatch: Arc<Mutex<DispatchState>>,
+    thermal: Arc<Mutex<ThermalState>>,
+    water_tank: Arc<Mutex<WaterTank>>,
+    solar: Arc<SolarModel>,
+    export_rate: f64,
+    // Load profile and the slot it was learned in; history only changes slot by slot
+    load_profile: Mutex<Option<(NaiveDateTime, LoadProfile)>>,
+    // Since when the next staggered restore has been waiting for room under the cap
+    restore_waiting_since: Mutex<Option<NaiveDateTime>>,
+}
+
+// Always-on household load (kW)
+const BASE_LOAD: f64 = 0.1;
+
+/// Splits the power of a group of units between them in proportion to their rating.
+fn share_by_rating(units: &[&Device], power: f64) -> HashMap<i64, f64> {
+    let rating: f64 = units.iter().map(|d| d.power_rating).sum();
+    units.iter()
+        .map(|d| (d.id, if rating > 0.0 { power * d.power_rating / rating } else { 0.0 }))
+        .collect()
 }
 
 impl Simulator {
-    pub fn new(pool: SqlitePool, user_overrides: Arc<Mutex<HashMap<i64, Instant>>>, load_shifting_enabled: Arc<Mutex<bool>>) -> Self {
+    pub fn new(state: &AppState) -> Self {
         Self { 
-            pool,
-            current_time: Arc::new(Mutex::new(Utc::now().naive_utc())),
-            user_overrides,
-            load_shifting_enabled,
+            pool: state.pool.clone(),
+            clock: state.clock.clone(),
+            battery: Mutex::new(Battery::default()),
+            rng: Mutex::new(state.config.seed.map_or_else(StdRng::from_os_rng, StdRng::seed_from_u64)),
+            load_shifting: state.load_shifting.clone(),
+            load_management: state.load_management.clone(),
+            events: state.events.clone(),
+            dispatch: state.dispatch.clone(),
+            thermal: state.thermal.clone(),
+            water_tank: state.water_tank.clone(),
+            solar: state.solar.clone(),
+            export_rate: state.config.export_rate,
+            load_profile: Mutex::new(None),
+            restore_waiting_since: Mutex::new(None),
         }
     }
 
     pub async fn start(&self) {
-        let mut interval = tokio::time::interval(Duration::from_secs(2));
+        // Resume from the last recorded state of charge so restarts don't reset the battery
+        if let Ok(Some(soc)) = sqlx::query_scalar!("SELECT battery_soc FROM energy_data ORDER BY id DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await
+        {
+            let mut battery = self.battery.lock().await;
+            battery.soc = soc.clamp(battery.min_soc, battery.max_soc);
+        }
+
         loop {
-            interval.tick().await;
-            
-            // Advance time by 30 minutes
-            {
-                let mut time = self.current_time.lock().await;
-                *time += chrono::Duration::minutes(30);
+            let (paused, tick_interval) = {
+                let clock = self.clock.clock.lock().await;
+                (clock.paused, Duration::from_millis(clock.tick_interval_ms))
+            };
+
+            // Clock changes interrupt the wait so a new speed or jump applies immediately
+            let ticked = tokio::select! {
+                _ = tokio::time::sleep(tick_interval), if !paused => true,
+                _ = self.clock.changed.notified() => false,
+            };
+
+            // Single steps requested through the API run even while paused
+            let steps = {
+                let mut clock = self.clock.clock.lock().await;
+                std::mem::take(&mut clock.pending_steps) + ticked as u32
+            };
+            for _ in 0..steps {
+                self.tick().await;
+            }
+        }
+    }
+
+    async fn tick(&self) {
+        // Take the sample time and advance simulated time by one step
+        let (now, step_minutes) = {
+            let mut clock = self.clock.clock.lock().await;
+            let now = clock.current_time;
+            let step = chrono::Duration::minutes(clock.step_minutes);
+            clock.current_time += step;
+            (now, clock.step_minutes)
+        };
+
+        if let Err(e) = self.generate_data(now, step_minutes).await {
+            tracing::error!("Simulation error: {}", e);
+        }
+    }
+
+    /// Forecast for the next 24 hours of steps: modelled solar scaled by the mean noise factor,
+    /// and the load learned from history, or the current device load until there is some.
+    /// `flexible` kW of that load is marked as sheddable.
+    async fn forecast_steps(&self, now: NaiveDateTime, step_minutes: i64, tariff: &TariffSchedule, devices: &[Device], flexible: f64) -> Vec<ForecastStep> {
+        let active_device_load: f64 = devices.iter().filter(|d| d.is_on).map(|d| d.power_rating).sum();
+        let current_load = BASE_LOAD + active_device_load + 0.1; // 0.1 = mean fluctuation
+        let profile = self.load_profile(now).await;
+        let horizon = (24 * 60 / step_minutes).max(1);
+        (0..horizon)
+            .map(|t| {
+                let time = now + chrono::Duration::minutes(step_minutes * t);
+                let hour = time.hour() as f64 + time.minute() as f64 / 60.0;
+                let load = profile.predict(time).unwrap_or(current_load);
+                ForecastStep {
+                    rate: tariff.rate_at(hour),
+                    solar: self.solar.generation(time) * 0.9,
+                    load: (load - flexible).max(BASE_LOAD), // The forecast includes the flexible load
+                    flexible,
+                }
+            })
+            .collect()
+    }
+
+    /// Load profile learned from the history before the slot containing `now`, learned again
+    /// only once the slot changes.
+    async fn load_profile(&self, now: NaiveDateTime) -> LoadProfile {
+        let slot = forecast::slot_start(now);
+        let mut cached = self.load_profile.lock().await;
+        if let Some((_, profile)) = cached.as_ref().filter(|(learned_in, _)| *learned_in == slot) {
+            return profile.clone();
+        }
+
+        match forecast::learn_profile(&self.pool, slot).await {
+            Ok(profile) => {
+                *cached = Some((slot, profile.clone()));
+                profile
+            }
+            Err(e) => {
+                tracing::warn!("Could not learn load profile, assuming current load: {}", e);
+                LoadProfile::default()
+            }
+        }
+    }
+
+    /// Battery power to apply this step under the optimal or predictive strategy, or `None` for
+    /// greedy dispatch. A new 24-hour schedule is planned whenever the current one does not cover
+    /// `now` or the battery has drifted from it; the predictive strategy has already re-planned
+    /// at the start of the step.
+    async fn planned_setpoint(&self, now: NaiveDateTime, step_minutes: i64, tariff: &TariffSchedule, devices: &[Device]) -> Option<f64> {
+        let mut dispatch = self.dispatch.lock().await;
+        if dispatch.strategy == DispatchStrategy::Greedy {
+            return None;
+        }
+
+        let battery = self.battery.lock().await.clone();
+        let covered = dispatch.schedule.as_ref()
+            .filter(|schedule| schedule.step_minutes == step_minutes)
+            .filter(|schedule| schedule.soc_at(now).is_some_and(|soc| (soc - battery.soc).abs() <= dispatch::SOC_DRIFT_PERCENT))
+            .and_then(|schedule| schedule.setpoint_at(now));
+        if covered.is_some() {
+            return covered;
+        }
+
+        let forecast = self.forecast_steps(now, step_minutes, tariff, devices, 0.0).await;
+        let schedule = dispatch::optimize(&battery, &forecast, now, step_minutes, self.export_rate, dispatch.shed_penalty);
+        tracing::info!("Planned battery schedule from {}: expected cost ${:.2}", now, schedule.total_cost);
+
+        let setpoint = schedule.setpoint_at(now);
+        dispatch.schedule = Some(schedule);
+        setpoint
+    }
+
+    /// Receding-horizon step of the predictive strategy: plans battery and shedding of the
+    /// `flexible` kW over the next 24 hours and returns whether to shed during this step.
+    /// The battery follows the same plan through `planned_setpoint`.
+    async fn replan(&self, now: NaiveDateTime, step_minutes: i64, tariff: &TariffSchedule, devices: &[Device], flexible: f64) -> bool {
+        let forecast = self.forecast_steps(now, step_minutes, tariff, devices, flexible).await;
+        let mut dispatch = self.dispatch.lock().await;
+        let battery = self.battery.lock().await.clone();
+        let plan = dispatch::optimize(&battery, &forecast, now, step_minutes, self.export_rate, dispatch.shed_penalty);
+        tracing::debug!("Re-planned from {}: expected cost ${:.2}", now, plan.total_cost);
+
+        let shed = plan.steps.first().is_some_and(|step| step.shed);
+        dispatch.schedule = Some(plan);
+        shed
+    }
+
+    /// Switches a device on or off on behalf of an automated controller, logs it and announces the change.
+    async fn set_device_state(&self, now: NaiveDateTime, device: &mut Device, is_on: bool, actor: Actor, reason: &str) -> Result<(), sqlx::Error> {
+        events::record(&self.pool, now, device, is_on, actor, reason).await?;
+        sqlx::query!("UPDATE devices SET is_on = ? WHERE id = ?", is_on, device.id)
+            .execute(&self.pool)
+            .await?;
+        device.is_on = is_on;
+        live::publish(&self.events, LiveEvent::DeviceState {
+            device_id: device.id,
+            is_on,
+            actor,
+            reason: reason.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Logs that load management held `device` at `allowed` kW instead of `requested` kW.
+    async fn record_curtailment(&self, now: NaiveDateTime, device: &Device, requested: f64, allowed: f64, limits: &LoadManagement, requested_import: f64) -> Result<(), sqlx::Error> {
+        let reason = format!(
+            "Import limit {:.1} kW: household would draw {:.1} kW",
+            limits.max_import_kw, requested_import
+        );
+        tracing::info!("Load Management: Curtailing {} from {:.2} kW to {:.2} kW", device.name, requested, allowed);
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO curtailments (timestamp, device_id, device_name, requested_power, allowed_power, reason)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+            now, device.id, device.name, requested, allowed, reason
+        )
+        .execute(&self.pool)
+        .await?;
+        live::publish(&self.events, LiveEvent::Curtailment(Curtailment {
+            id: result.last_insert_rowid(),
+            timestamp: now,
+            device_id: device.id,
+            device_name: device.name.clone(),
+            requested_power: requested,
+            allowed_power: allowed,
+            reason,
+        }));
+        Ok(())
+    }
+
+    /// Adds `energy` kWh to the charging session open on `device_id` at `now`.
+    async fn record_ev_delivery(&self, now: NaiveDateTime, step_minutes: i64, device_id: i64, energy: f64) -> Result<(), sqlx::Error> {
+        let ended_after = now - chrono::Duration::minutes(step_minutes);
+        sqlx::query!(
+            r#"
+            UPDATE ev_sessions SET delivered_kwh = delivered_kwh + ?
+            WHERE id = (
+                SELECT id FROM ev_sessions
+                WHERE device_id = ? AND arrival <= ? AND departure > ?
+                ORDER BY departure DESC
+                LIMIT 1
+            )
+            "#,
+            energy, device_id, now, ended_after
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Power (kW) of every appliance running a booked program this step. Appliances are switched
+    /// on when their program starts and off once it has finished; in between only a user override may touch them.
+    async fn appliance_power(&self, now: NaiveDateTime, step_minutes: i64, devices: &mut [Device], overridden: &HashSet<i64>) -> Result<HashMap<i64, f64>, sqlx::Error> {
+        let mut running = HashMap::new();
+        let step_end = now + chrono::Duration::minutes(step_minutes);
+        // Runs that finished during the previous step are still picked up so the appliance gets switched off
+        let ended_after = now - chrono::Duration::minutes(step_minutes);
+
+        for device in devices.iter_mut().filter(|d| !overridden.contains(&d.id)) {
+            let Some(program) = appliance::program_for(&device.device_type) else { continue };
+            let run = sqlx::query_as!(
+                ApplianceRun,
+                r#"
+                SELECT id, device_id, program, earliest_start, finish_by, start_time, end_time
+                FROM appliance_runs
+                WHERE device_id = ? AND start_time < ? AND end_time > ?
+                ORDER BY start_time DESC
+                LIMIT 1
+                "#,
+                device.id,
+                step_end,
+                ended_after
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+            let Some(run) = run else { continue };
+
+            let offset = (now - run.start_time).num_minutes();
+            let power = program.mean_power(device.power_rating, offset, step_minutes);
+
+            let should_be_on = power > 0.0;
+            if device.is_on != should_be_on {
+                tracing::info!("Appliance Scheduler: {} {} program on {}", if should_be_on { "Starting" } else { "Finished" }, run.program, device.name);
+                self.set_device_state(now, device, should_be_on, Actor::Scheduler, "Appliance program").await?;
             }
+            running.insert(device.id, power);
+        }
+
+        Ok(running)
+    }
+
+    /// Charging power (kW) for every EV charger with an open session, following a schedule
+    /// that meets the session's deadline at the lowest cost. Switches chargers on and off
+    /// to match. Chargers under a user override are skipped.
+    async fn ev_charging_power(&self, now: NaiveDateTime, step_minutes: i64, tariff: &TariffSchedule, devices: &mut [Device], overridden: &HashSet<i64>) -> Result<HashMap<i64, f64>, sqlx::Error> {
+        let step_hours = step_minutes as f64 / 60.0;
+        let mut charging = HashMap::new();
+
+        // Expected load of everything else in the house, to estimate the solar surplus
+        let other_load: f64 = BASE_LOAD + 0.1 + devices.iter()
+            .filter(|d| d.is_on && d.device_type != EV_CHARGER)
+            .map(|d| d.power_rating)
+            .sum::<f64>();
 
-            if let Err(e) = self.generate_data().await {
-                tracing::error!("Simulation error: {}", e);
+        // Sessions that ended during the previous step are still picked up so the charger gets switched off
+        let ended_after = now - chrono::Duration::minutes(step_minutes);
+
+        for device in devices.iter_mut().filter(|d| d.device_type == EV_CHARGER && !overridden.contains(&d.id)) {
+            let session = sqlx::query_as!(
+                EvSession,
+                r#"
+                SELECT id, device_id, arrival, departure, required_kwh, max_rate_kw, delivered_kwh
+                FROM ev_sessions
+                WHERE device_id = ? AND arrival <= ? AND departure > ?
+                ORDER BY departure DESC
+                LIMIT 1
+                "#,
+                device.id,
+                now,
+                ended_after
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+            let Some(session) = session else { continue };
+
+            let remaining_steps = (session.departure - now).num_minutes() / step_minutes;
+            let slots: Vec<ChargeSlot> = (0..remaining_steps.max(0))
+                .map(|t| {
+                    let time = now + chrono::Duration::minutes(step_minutes * t);
+                    let hour = time.hour() as f64 + time.minute() as f64 / 60.0;
+                    ChargeSlot { rate: tariff.rate_at(hour), solar_surplus: self.solar.generation(time) * 0.9 - other_load }
+                })
+                .collect();
+            let plan = ev::schedule_charging(
+                &slots,
+                session.required_kwh - session.delivered_kwh,
+                session.max_rate_kw.min(device.power_rating),
+                step_hours,
+                self.export_rate,
+            );
+            let power = plan.first().copied().unwrap_or(0.0);
+
+            let should_be_on = power > 0.0;
+            if device.is_on != should_be_on {
+                tracing::info!("EV Scheduler: Turning {} {}", if should_be_on { "ON" } else { "OFF" }, device.name);
+                let remaining = session.required_kwh - session.delivered_kwh;
+                let reason = if should_be_on {
+                    format!("Charging at ${:.2}/kWh, {:.1} kWh to go before {}", slots[0].rate, remaining, session.departure)
+                } else if remaining <= 1e-9 {
+                    "Charging session complete".to_string()
+                } else if now >= session.departure {
+                    format!("Departure time reached with {:.1} kWh undelivered", remaining)
+                } else {
+                    format!("Waiting for cheaper energy, {:.1} kWh to go before {}", remaining, session.departure)
+                };
+                self.set_device_state(now, device, should_be_on, Actor::Scheduler, &reason).await?;
             }
+            charging.insert(device.id, power);
         }
+
+        Ok(charging)
     }
 
-    async fn generate_data(&self) -> Result<(), sqlx::Error> {
-        let now = *self.current_time.lock().await;
+    /// Electrical load (kW) of every HVAC unit that is on, from the building's thermal model,
+    /// and the heat (kW, negative = cooling) they deliver at that load.
+    /// Pre-conditioning for the peak is part of load shifting and only happens while it is enabled.
+    async fn hvac_power(&self, now: NaiveDateTime, step_hours: f64, tariff: &TariffSchedule, policy: &LoadShiftingPolicy, shifting: bool, devices: &[Device]) -> (HashMap<i64, f64>, f64) {
+        let units: Vec<&Device> = devices.iter().filter(|d| d.is_on && d.device_type == HVAC).collect();
+        let rating: f64 = units.iter().map(|d| d.power_rating).sum();
+
+        let hour = now.hour() as f64 + now.minute() as f64 / 60.0;
+        let thermal = self.thermal.lock().await.in_season(now.ordinal(), self.solar.array.latitude_deg);
+        let mode = if shifting { thermal.mode(now, step_hours, |t| policy.is_peak(tariff, t)) } else { HvacMode::Hold };
+        let heat = thermal.hvac_heat(hour, step_hours, mode, rating);
+        (share_by_rating(&units, thermal.electric_power(heat)), heat)
+    }
+
+    /// Element power (kW) of every water heater that is on.
+    /// With load shifting enabled the tank stores solar surplus and rides through peak hours.
+    async fn water_heater_power(&self, hour: f64, step_hours: f64, is_peak: bool, smart: bool, solar_surplus: f64, devices: &[Device]) -> HashMap<i64, f64> {
+        let units: Vec<&Device> = devices.iter().filter(|d| d.is_on && d.device_type == WATER_HEATER).collect();
+        let rating: f64 = units.iter().map(|d| d.power_rating).sum();
+        let strategy = if smart {
+            HeatingStrategy::Smart { is_peak, solar_surplus }
+        } else {
+            HeatingStrategy::Thermostat
+        };
+
+        let tank = self.water_tank.lock().await;
+        let draw = water_heater::hot_water_draw(hour, step_hours);
+        share_by_rating(&units, tank.heating_power(step_hours, rating, draw, strategy))
+    }
+
+    async fn generate_data(&self, now: NaiveDateTime, step_minutes: i64) -> Result<(), sqlx::Error> {
+        let step_hours = step_minutes as f64 / 60.0;
 
         // Fetch devices to calculate real load
         let mut devices = sqlx::query_as!(
@@ -55,105 +429,302 @@ impl Simulator {
         .fetch_all(&self.pool)
         .await?;
 
+        // Reload the tariff every tick so edits take effect immediately
+        let tariff = match TariffSchedule::load(&self.pool).await {
+            Ok(tariff) => tariff,
+            Err(e) => {
+                tracing::warn!("Invalid tariff configuration, using default: {}", e);
+                TariffSchedule::default()
+            }
+        };
+
         // Automated Demand Response (Load Shifting)
         let hour = now.hour() as f64 + now.minute() as f64 / 60.0;
-        let is_peak = hour >= 18.0 && hour < 22.0;
-        let is_post_peak = hour >= 22.0 && hour < 22.5; // 30 mins after peak to restore
-
-        let shifting_enabled = *self.load_shifting_enabled.lock().await;
+        let policy = self.load_shifting.lock().await.clone();
+        let is_peak = policy.is_peak(&tariff, now);
+        let shed = events::shed_devices(&self.pool).await?;
 
+        // Devices under a user override are held in the chosen state and left alone by every
+        // automated controller
+        let overrides = overrides::active(&self.pool, now).await?;
         for device in &mut devices {
-            if shifting_enabled && device.priority < 2 { // Low priority
-                if is_peak && device.is_on {
-                    // Check for user override
-                    let last_override = {
-                        let overrides = self.user_overrides.lock().await;
-                        overrides.get(&device.id).cloned()
-                    };
+            if let Some(&is_on) = overrides.get(&device.id).filter(|&&is_on| is_on != device.is_on) {
+                tracing::info!("Override: Holding {} {}", device.name, if is_on { "ON" } else { "OFF" });
+                self.set_device_state(now, device, is_on, Actor::User, "Manual override").await?;
+            }
+        }
+        let overridden: HashSet<i64> = overrides.keys().copied().collect();
 
-                    let should_turn_off = match last_override {
-                        Some(time) => time.elapsed().as_secs() > 24, // Override lasts 24 seconds(6 simulated hours)
-                        None => true,
-                    };
+        // Appliance programs go first: a running cycle is never interrupted by the load shifter
+        let mut device_power = self.appliance_power(now, step_minutes, &mut devices, &overridden).await?;
+        let running_programs: HashSet<i64> = device_power.keys().copied().collect();
+
+        let controlled = |device: &Device| device_power.contains_key(&device.id)
+            || device.device_type == WATER_HEATER
+            || overridden.contains(&device.id);
+        let predictive = self.dispatch.lock().await.strategy == DispatchStrategy::Predictive;
+        // Whether shed load is being held off this step; otherwise it is restored per the policy
+        let mut shedding = is_peak;
 
-                    if should_turn_off {
+        if predictive {
+            // Shed low-priority load whenever the plan for the next 24 hours says it pays off
+            let flexible: f64 = devices.iter()
+                .filter(|d| policy.sheds(d) && !controlled(d) && (d.is_on || shed.contains(&d.id)))
+                .map(|d| d.power_rating)
+                .sum();
+            shedding = self.replan(now, step_minutes, &tariff, &devices, flexible).await;
+            for device in devices.iter_mut().filter(|d| policy.sheds(d) && !controlled(d)) {
+                if shedding && device.is_on {
+                    tracing::info!("Predictive Control: Turning OFF {}", device.name);
+                    let reason = format!("Predictive control: shedding at ${:.2}/kWh", tariff.rate_at(hour));
+                    self.set_device_state(now, device, false, Actor::LoadShifter, &reason).await?;
+                } else if !shedding && !device.is_on && shed.contains(&device.id) && policy.restore == RestoreStrategy::Immediate {
+                    tracing::info!("Predictive Control: Restoring {}", device.name);
+                    self.set_device_state(now, device, true, Actor::LoadShifter, "Predictive control: load restored").await?;
+                }
+            }
+        } else {
+            for device in &mut devices {
+                if policy.sheds(device) && !controlled(device) { // Low priority
+                    if is_peak && device.is_on {
                         tracing::info!("Peak Shaving: Turning OFF {}", device.name);
-                        sqlx::query!("UPDATE devices SET is_on = 0 WHERE id = ?", device.id)
-                            .execute(&self.pool)
-                            .await?;
-                        device.is_on = false; // Update local state for load calc
+                        let reason = format!("Peak shaving at ${:.2}/kWh", tariff.rate_at(hour));
+                        self.set_device_state(now, device, false, Actor::LoadShifter, &reason).await?;
+                    } else if !is_peak && !device.is_on && shed.contains(&device.id) && policy.restore == RestoreStrategy::Immediate {
+                        // Restore after peak
+                        tracing::info!("Peak Over: Restoring {}", device.name);
+                        self.set_device_state(now, device, true, Actor::LoadShifter, "Peak over").await?;
                     }
-                } else if is_post_peak && !device.is_on {
-                    // Restore after peak
-                    tracing::info!("Peak Over: Restoring {}", device.name);
-                    sqlx::query!("UPDATE devices SET is_on = 1 WHERE id = ?", device.id)
-                        .execute(&self.pool)
-                        .await?;
-                    device.is_on = true;
                 }
             }
         }
         
-        let (solar_generation, home_consumption, battery_soc) = {
-            let mut rng = rand::rng();
-            
-            // Solar: Peak at noon (simple Gaussian-like curve)
-            let hour = now.hour() as f64 + now.minute() as f64 / 60.0;
-            let solar_potential = if hour > 6.0 && hour < 18.0 {
-                let peak = 2.0; // 2kW peak
-                let x = (hour - 12.0) / 3.0; // Width factor
-                peak * (-x * x).exp()
-            } else {
-                0.0
-            };
-            let solar_generation = (solar_potential * rng.random_range(0.8..1.0)).max(0.0);
+        // Devices whose draw differs from their power rating this step
+        let ev_charging = self.ev_charging_power(now, step_minutes, &tariff, &mut devices, &overridden).await?;
+        device_power.extend(&ev_charging);
+        // Units the user has taken over simply hold the setpoint or heat on demand
+        let user_controls = |device_type: &str| devices.iter().any(|d| d.device_type == device_type && overridden.contains(&d.id));
+        let (hvac_power, hvac_heat) = self.hvac_power(now, step_hours, &tariff, &policy, policy.enabled && !user_controls(HVAC), &devices).await;
+        device_power.extend(hvac_power);
 
-            // Load: Base + Active Devices
-            let base_load = 0.1; // 100W base load (always on stuff)
+        let (solar_generation, fluctuation) = {
+            let mut rng = self.rng.lock().await;
             
-            // Calculate load from active devices
-            let active_device_load: f64 = devices.iter()
-                .filter(|d| d.is_on)
-                .map(|d| d.power_rating)
-                .sum();
+            // Solar: PV model for the season and weather, with some noise on top
+            let solar_generation = (self.solar.generation(now) * rng.random_range(0.8..1.0)).max(0.0);
 
             // Random fluctuation (noise) to make it look real
             // Range: -0.1kW to +0.3kW
             let fluctuation = rng.random_range(-0.1..0.3);
-            
-            let home_consumption = (base_load + active_device_load + fluctuation).max(0.0);
-            
-            // Mock SOC
-            let battery_soc = 50.0 + rng.random_range(-1.0..1.0);
-            
-            (solar_generation, home_consumption, battery_soc)
+
+            (solar_generation, fluctuation)
         };
 
-        // Battery logic (simplified)
-        let net_energy = solar_generation - home_consumption;
-        
-        let (battery_charge, battery_discharge, grid_import, grid_export) = if net_energy > 0.0 {
-            // Excess energy -> Charge battery or Export
-            let charge = net_energy.min(3.0); // Max charge 3kW
-            let export = net_energy - charge;
-            (charge, 0.0, 0.0, export)
+        // Load: Base + Active Devices
+        let base_load = BASE_LOAD; // 100W base load (always on stuff)
+
+        // Water heaters soak up whatever solar the rest of the house leaves
+        let other_load: f64 = devices.iter()
+            .filter(|d| d.is_on && d.device_type != WATER_HEATER)
+            .map(|d| device_power.get(&d.id).copied().unwrap_or(d.power_rating))
+            .sum();
+        let solar_surplus = solar_generation - (base_load + other_load + fluctuation);
+        let water_heating = self.water_heater_power(hour, step_hours, is_peak, policy.enabled && !user_controls(WATER_HEATER), solar_surplus, &devices).await;
+        device_power.extend(water_heating);
+
+        // Staggered restore: one shed device per interval, and only while import stays under the
+        // cap unless it has waited too long
+        let mut waiting_since = self.restore_waiting_since.lock().await;
+        let waiting: Vec<&Device> = devices.iter()
+            .filter(|d| !d.is_on && shed.contains(&d.id) && !overridden.contains(&d.id))
+            .collect();
+        if shedding || !policy.enabled || policy.restore != RestoreStrategy::Staggered || waiting.is_empty() {
+            *waiting_since = None;
         } else {
-            // Deficit -> Discharge battery or Import
-            let deficit = -net_energy;
-            let discharge = deficit.min(3.0); // Max discharge 3kW
-            let import = deficit - discharge;
-            (0.0, discharge, import, 0.0)
-        }; 
+            let due = match events::last_restore(&self.pool, now).await? {
+                Some(last) => now - last >= chrono::Duration::minutes(policy.restore_interval_minutes),
+                None => true,
+            };
+            // Restarted when the clock is turned back
+            let since = waiting_since.filter(|&since| since <= now).unwrap_or(now);
+            *waiting_since = Some(since);
+            let overdue = now - since >= chrono::Duration::minutes(MAX_RESTORE_DELAY_MINUTES);
+            if due {
+                let load: f64 = devices.iter()
+                    .filter(|d| d.is_on)
+                    .map(|d| device_power.get(&d.id).copied().unwrap_or(d.power_rating))
+                    .sum();
+                let import = (base_load + load + fluctuation - solar_generation).max(0.0);
+                let next = policy.next_restore(&waiting, import, overdue).map(|d| d.id);
+                if let Some(device) = next.and_then(|id| devices.iter_mut().find(|d| d.id == id)) {
+                    tracing::info!("Peak Over: Restoring {} (staggered)", device.name);
+                    let reason = if import + device.power_rating <= policy.restore_import_cap_kw {
+                        format!("Peak over, staggered restore at {:.1} kW import", import + device.power_rating)
+                    } else {
+                        format!("Peak over, restored above the {:.1} kW cap after waiting {} minutes", policy.restore_import_cap_kw, (now - since).num_minutes())
+                    };
+                    self.set_device_state(now, device, true, Actor::LoadShifter, &reason).await?;
+                    *waiting_since = Some(now);
+                }
+            }
+        }
+        drop(waiting_since);
 
-        sqlx::query!(
+        // Battery setpoint for this step, decided before load management so that the discharge
+        // it will deliver counts towards staying under the import limit
+        let setpoint = self.planned_setpoint(now, step_minutes, &tariff, &devices).await;
+        let discharge_available = {
+            let battery = self.battery.lock().await;
+            match setpoint {
+                Some(power) if power >= 0.0 => 0.0,
+                Some(power) => (-power).min(battery.max_discharge_power(step_hours)),
+                None => battery.max_discharge_power(step_hours),
+            }
+        };
+
+        // Load management: curtail the lowest priority load to stay within the import limit.
+        // Devices it switched off earlier compete at their rating and come back once they fit,
+        // unless the user holds them or the load shifter would shed them right away.
+        let limits = self.load_management.lock().await.clone();
+        let curtailed = events::curtailed_devices(&self.pool).await?;
+        let held_off = |d: &Device| curtailed.contains(&d.id) && !overridden.contains(&d.id) && !(shedding && policy.sheds(d));
+        let candidates: Vec<usize> = (0..devices.len())
+            .filter(|&i| devices[i].is_on || held_off(&devices[i]))
+            .collect();
+        let loads: Vec<Load> = candidates.iter()
+            .map(|&i| &devices[i])
+            .map(|d| Load {
+                priority: d.priority,
+                power: device_power.get(&d.id).copied().unwrap_or(d.power_rating),
+                modulating: matches!(d.device_type.as_str(), EV_CHARGER | HVAC | WATER_HEATER),
+                protected: overridden.contains(&d.id) || running_programs.contains(&d.id),
+            })
+            .collect();
+        let fixed_load = base_load + fluctuation - solar_generation - discharge_available;
+        let allowed = limits.allocate(&loads, fixed_load);
+        let requested_import = fixed_load + loads.iter().map(|l| l.power).sum::<f64>();
+        for ((&i, load), power) in candidates.iter().zip(&loads).zip(allowed) {
+            let device = &mut devices[i];
+            if !device.is_on {
+                // Switched back on now; it draws from the next step
+                if power >= load.power - 1e-9 {
+                    tracing::info!("Load Management: Restoring {}", device.name);
+                    let reason = format!("Import limit {:.1} kW: load restored", limits.max_import_kw);
+                    self.set_device_state(now, device, true, Actor::LoadManager, &reason).await?;
+                    device_power.insert(device.id, 0.0);
+                }
+                continue;
+            }
+            if power < load.power - 1e-9 {
+                self.record_curtailment(now, device, load.power, power, &limits, requested_import).await?;
+            }
+            if power <= 1e-9 && load.power > 1e-9 && !load.protected {
+                // Nothing left to draw: show the device as off until it fits again
+                let reason = format!("Import limit {:.1} kW: household would draw {:.1} kW", limits.max_import_kw, requested_import);
+                self.set_device_state(now, device, false, Actor::LoadManager, &reason).await?;
+            }
+            device_power.insert(device.id, power);
+        }
+
+        // Advance the thermal models and EV sessions with the power actually drawn
+        let drawn_by = |device_type: &str| -> f64 {
+            devices.iter()
+                .filter(|d| d.is_on && d.device_type == device_type)
+                .map(|d| device_power.get(&d.id).copied().unwrap_or(0.0))
+                .sum()
+        };
+        {
+            // The outdoor temperature follows the season, as in the analysis
+            let mut thermal = self.thermal.lock().await;
+            let mut seasonal = thermal.in_season(now.ordinal(), self.solar.array.latitude_deg);
+            let planned = seasonal.electric_power(hvac_heat);
+            let scale = if planned > 0.0 { drawn_by(HVAC) / planned } else { 1.0 };
+            seasonal.advance(hour, hvac_heat * scale, step_hours);
+            thermal.indoor_temp = seasonal.indoor_temp;
+        }
+        self.water_tank.lock().await.advance(drawn_by(WATER_HEATER), water_heater::hot_water_draw(hour, step_hours), step_hours);
+        for device_id in ev_charging.keys() {
+            let delivered = device_power.get(device_id).copied().unwrap_or(0.0) * step_hours;
+            if delivered > 0.0 {
+                self.record_ev_delivery(now, step_minutes, *device_id, delivered).await?;
+            }
+        }
+
+        // Calculate load from active devices, per device for metering
+        let device_loads: Vec<(i64, f64)> = devices.iter()
+            .map(|d| (d.id, if d.is_on { device_power.get(&d.id).copied().unwrap_or(d.power_rating) } else { 0.0 }))
+            .collect();
+        let active_device_load: f64 = device_loads.iter().map(|(_, power)| power).sum();
+
+        let home_consumption = (base_load + active_device_load + fluctuation).max(0.0);
+
+        // Battery logic: follow the optimal schedule if enabled, otherwise
+        // charge on surplus and discharge on deficit, within the battery's limits
+        let net_energy = solar_generation - home_consumption;
+
+        // A planned discharge only covers the household's actual deficit, unless the plan meant to export
+        let export_planned = self.dispatch.lock().await.schedule.as_ref()
+            .and_then(|schedule| schedule.step_at(now))
+            .is_some_and(|step| step.grid_export > 1e-9);
+        let deficit = (home_consumption - solar_generation).max(0.0);
+
+        let (battery_charge, battery_discharge, grid_import, grid_export, battery_soc) = {
+            let mut battery = self.battery.lock().await;
+            let (charge, discharge) = match setpoint {
+                Some(power) if power >= 0.0 => {
+                    // Grid charging counts against the import limit too
+                    let headroom = limits.headroom(home_consumption - solar_generation);
+                    (battery.charge(power.min(headroom), step_hours), 0.0)
+                }
+                Some(power) if export_planned => (0.0, battery.discharge(-power, step_hours)),
+                Some(power) => (0.0, battery.discharge((-power).min(deficit), step_hours)),
+                None if net_energy > 0.0 => (battery.charge(net_energy, step_hours), 0.0),
+                None => (0.0, battery.discharge(-net_energy, step_hours)),
+            };
+            let net_energy = net_energy - charge + discharge;
+            let (import, export) = if net_energy > 0.0 {
+                (0.0, net_energy)
+            } else {
+                (-net_energy, 0.0)
+            };
+            (charge, discharge, import, export, battery.soc)
+        };
+
+        let cost = grid_import * tariff.rate_at(hour) * step_hours;
+
+        let result = sqlx::query!(
             r#"
-            INSERT INTO energy_data (timestamp, grid_import, grid_export, solar_generation, battery_charge, battery_discharge, home_consumption, battery_soc)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO energy_data (timestamp, grid_import, grid_export, solar_generation, battery_charge, battery_discharge, home_consumption, battery_soc, cost)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
-            now, grid_import, grid_export, solar_generation, battery_charge, battery_discharge, home_consumption, battery_soc
+            now, grid_import, grid_export, solar_generation, battery_charge, battery_discharge, home_consumption, battery_soc, cost
         )
         .execute(&self.pool)
         .await?;
+
+        // Per-device metering
+        for (device_id, power) in device_loads {
+            let energy = power * step_hours;
+            sqlx::query!(
+                "INSERT INTO device_readings (device_id, timestamp, power, energy) VALUES (?, ?, ?, ?)",
+                device_id, now, power, energy
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+
+        live::publish(&self.events, LiveEvent::Energy(EnergyData {
+            id: result.last_insert_rowid(),
+            timestamp: now,
+            grid_import,
+            grid_export,
+            solar_generation,
+            battery_charge,
+            battery_discharge,
+            home_consumption,
+            battery_soc,
+            cost,
+        }));
         
         tracing::info!("Generated: Solar={:.2}kW, Load={:.2}kW, SOC={:.1}%", solar_generation, home_consumption, battery_soc);
         Ok(())
@@ -0,0 +1,194 @@
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::io::Read;
+use std::path::Path;
+
+// Extraterrestrial beam irradiance (W/m²) and ground reflectance used by the clear-sky model
+const SOLAR_CONSTANT: f64 = 1353.0;
+const ALBEDO: f64 = 0.2;
+
+/// A fixed PV array.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PvArray {
+    pub capacity_kwp: f64, // kW at 1000 W/m²
+    pub tilt_deg: f64, // 0 = flat, 90 = vertical
+    pub azimuth_deg: f64, // Direction the panels face, clockwise from north (180 = south)
+    pub latitude_deg: f64, // Negative in the southern hemisphere
+    pub performance_ratio: f64, // Inverter, wiring, soiling and temperature losses
+}
+
+impl Default for PvArray {
+    fn default() -> Self {
+        Self { capacity_kwp: 2.5, tilt_deg: 30.0, azimuth_deg: 180.0, latitude_deg: 35.0, performance_ratio: 0.85 }
+    }
+}
+
+/// Position of the sun: cosine of the zenith angle and azimuth (degrees clockwise from north).
+/// `None` while the sun is below the horizon. `hour` is local solar time.
+fn sun_position(latitude_deg: f64, day_of_year: u32, hour: f64) -> Option<(f64, f64)> {
+    let latitude = latitude_deg.to_radians();
+    let declination = 23.45f64.to_radians() * (2.0 * PI * (284.0 + day_of_year as f64) / 365.0).sin();
+    let hour_angle = (15.0 * (hour - 12.0)).to_radians();
+
+    let cos_zenith = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    if cos_zenith <= 0.0 {
+        return None;
+    }
+    let sin_zenith = (1.0 - cos_zenith * cos_zenith).sqrt();
+    let cos_azimuth = ((declination.sin() - cos_zenith * latitude.sin()) / (sin_zenith * latitude.cos())).clamp(-1.0, 1.0);
+    let azimuth = cos_azimuth.acos().to_degrees();
+    Some((cos_zenith, if hour_angle > 0.0 { 360.0 - azimuth } else { azimuth }))
+}
+
+/// Clear-sky beam (normal to the sun) and diffuse irradiance (W/m²), from the Meinel air mass model.
+fn clear_sky(cos_zenith: f64) -> (f64, f64) {
+    let air_mass = (1.0 / cos_zenith).min(38.0);
+    let beam = SOLAR_CONSTANT * 0.7f64.powf(air_mass.powf(0.678));
+    (beam, 0.1 * beam)
+}
+
+impl PvArray {
+    /// Output (kW) under a clear sky on `day_of_year` at `hour` (local solar time).
+    pub fn clear_sky_output(&self, day_of_year: u32, hour: f64) -> f64 {
+        let Some((cos_zenith, sun_azimuth)) = sun_position(self.latitude_deg, day_of_year, hour) else {
+            return 0.0;
+        };
+        let (beam, diffuse) = clear_sky(cos_zenith);
+        let global = beam * cos_zenith + diffuse;
+
+        // Irradiance on the plane of the array: direct beam, sky diffuse and ground reflection
+        let tilt = self.tilt_deg.to_radians();
+        let sin_zenith = (1.0 - cos_zenith * cos_zenith).sqrt();
+        let cos_incidence = cos_zenith * tilt.cos()
+            + sin_zenith * tilt.sin() * (sun_azimuth - self.azimuth_deg).to_radians().cos();
+        let plane = beam * cos_incidence.max(0.0)
+            + diffuse * (1.0 + tilt.cos()) / 2.0
+            + global * ALBEDO * (1.0 - tilt.cos()) / 2.0;
+
+        self.capacity_kwp * plane / 1000.0 * self.performance_ratio
+    }
+}
+
+/// One hour of measured or forecast weather. Either field may be left empty.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WeatherHour {
+    pub day_of_year: u32,
+    pub hour: u32,
+    pub ghi: Option<f64>, // Global horizontal irradiance, W/m²
+    pub cloud_cover: Option<f64>, // 0 (clear) to 1 (overcast)
+}
+
+/// Hourly weather for a year, e.g. from a typical meteorological year (TMY) file.
+#[derive(Debug, Clone, Default)]
+pub struct WeatherData {
+    hours: HashMap<(u32, u32), WeatherHour>,
+}
+
+impl WeatherData {
+    /// Reads a CSV with the columns `day_of_year,hour,ghi,cloud_cover`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+
+    pub fn from_reader(reader: impl Read) -> Result<Self, Box<dyn Error>> {
+        let mut hours = HashMap::new();
+        for row in csv::Reader::from_reader(reader).deserialize() {
+            let row: WeatherHour = row?;
+            hours.insert((row.day_of_year, row.hour), row);
+        }
+        Ok(Self { hours })
+    }
+
+    pub fn len(&self) -> usize {
+        self.hours.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hours.is_empty()
+    }
+
+    /// Fraction of the clear-sky output reaching the array. Measured irradiance wins over
+    /// cloud cover, which is converted with the Kasten-Czeplak relation. Hours missing from
+    /// the file count as clear.
+    fn clearness(&self, latitude_deg: f64, day_of_year: u32, hour: f64) -> f64 {
+        let Some(weather) = self.hours.get(&(day_of_year, hour.floor() as u32)) else {
+            return 1.0;
+        };
+        if let Some(ghi) = weather.ghi {
+            let clear_ghi = sun_position(latitude_deg, day_of_year, hour)
+                .map(|(cos_zenith, _)| {
+                    let (beam, diffuse) = clear_sky(cos_zenith);
+                    beam * cos_zenith + diffuse
+                })
+                .unwrap_or(0.0);
+            return if clear_ghi > 1.0 { (ghi / clear_ghi).clamp(0.0, 1.2) } else { 0.0 };
+        }
+        match weather.cloud_cover {
+            Some(cover) => 1.0 - 0.75 * cover.clamp(0.0, 1.0).powf(3.4),
+            None => 1.0,
+        }
+    }
+}
+
+/// PV generation model shared by the simulator, its forecasts and the analysis.
+#[derive(Debug, Clone, Default)]
+pub struct SolarModel {
+    pub array: PvArray,
+    pub weather: Option<WeatherData>, // Clear sky every day when absent
+}
+
+impl SolarModel {
+    /// Expected output (kW) at `time`, treating clock time as solar time.
+    pub fn generation(&self, time: NaiveDateTime) -> f64 {
+        let day_of_year = time.ordinal();
+        let hour = time.hour() as f64 + time.minute() as f64 / 60.0;
+        let clear = self.array.clear_sky_output(day_of_year, hour);
+        match &self.weather {
+            Some(weather) => clear * weather.clearness(self.array.latitude_deg, day_of_year, hour),
+            None => clear,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_follows_season_and_orientation() {
+        let array = PvArray::default();
+        let (summer, winter) = (array.clear_sky_output(172, 12.0), array.clear_sky_output(355, 12.0));
+        assert!(summer > 1.9 && summer < array.capacity_kwp, "summer noon {}", summer);
+        assert!(winter < summer * 0.85, "winter noon {} vs {}", winter, summer);
+        assert_eq!(array.clear_sky_output(172, 3.0), 0.0);
+        assert_eq!(array.clear_sky_output(355, 18.0), 0.0);
+
+        // East-facing panels produce more in the morning than in the afternoon
+        let east = PvArray { azimuth_deg: 90.0, ..PvArray::default() };
+        assert!(east.clear_sky_output(172, 9.0) > east.clear_sky_output(172, 15.0));
+
+        // Panels facing the equator beat panels facing away from it
+        let north = PvArray { azimuth_deg: 0.0, ..PvArray::default() };
+        assert!(north.clear_sky_output(355, 12.0) < winter * 0.5);
+    }
+
+    #[test]
+    fn test_weather_file_scales_output() {
+        let csv = "day_of_year,hour,ghi,cloud_cover\n172,12,,1.0\n172,13,0,\n172,14,,\n";
+        let weather = WeatherData::from_reader(csv.as_bytes()).unwrap();
+        assert_eq!(weather.len(), 3);
+
+        let clear = SolarModel::default();
+        let cloudy = SolarModel { weather: Some(weather), ..SolarModel::default() };
+        let at = |hour: u32| chrono::NaiveDate::from_yo_opt(2025, 172).unwrap().and_hms_opt(hour, 0, 0).unwrap();
+
+        assert!((cloudy.generation(at(12)) - 0.25 * clear.generation(at(12))).abs() < 1e-9);
+        assert_eq!(cloudy.generation(at(13)), 0.0);
+        // Empty fields and hours missing from the file fall back to clear sky
+        assert_eq!(cloudy.generation(at(14)), clear.generation(at(14)));
+        assert_eq!(cloudy.generation(at(10)), clear.generation(at(10)));
+    }
+}
@@ -0,0 +1,180 @@
+use std::error::Error;
+use std::fmt;
+use sqlx::SqlitePool;
+use crate::models::Tariff;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TariffError {
+    InvalidRange { name: String, start_hour: i64, end_hour: i64 },
+    NegativeRate { name: String, rate: f64 },
+    Overlap { first: String, second: String },
+    Gap { from_hour: i64, to_hour: i64 },
+}
+
+impl fmt::Display for TariffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TariffError::InvalidRange { name, start_hour, end_hour } => {
+                write!(f, "Tariff '{}' has invalid hours {}-{} (expected 0 <= start < end <= 24)", name, start_hour, end_hour)
+            }
+            TariffError::NegativeRate { name, rate } => {
+                write!(f, "Tariff '{}' has negative rate {}", name, rate)
+            }
+            TariffError::Overlap { first, second } => {
+                write!(f, "Tariffs '{}' and '{}' overlap", first, second)
+            }
+            TariffError::Gap { from_hour, to_hour } => {
+                write!(f, "No tariff covers hours {}-{}", from_hour, to_hour)
+            }
+        }
+    }
+}
+
+impl Error for TariffError {}
+
+/// Checks that every band has a valid hour range and rate and that no two bands overlap.
+/// With `full_coverage` the bands must also cover the whole day from 00:00 to 24:00.
+pub fn validate(bands: &[Tariff], full_coverage: bool) -> Result<(), TariffError> {
+    for band in bands {
+        if band.start_hour < 0 || band.end_hour > 24 || band.start_hour >= band.end_hour {
+            return Err(TariffError::InvalidRange {
+                name: band.name.clone(),
+                start_hour: band.start_hour,
+                end_hour: band.end_hour,
+            });
+        }
+        if band.rate < 0.0 || band.rate.is_nan() {
+            return Err(TariffError::NegativeRate { name: band.name.clone(), rate: band.rate });
+        }
+    }
+
+    let mut sorted: Vec<&Tariff> = bands.iter().collect();
+    sorted.sort_by_key(|b| b.start_hour);
+
+    for pair in sorted.windows(2) {
+        if pair[1].start_hour < pair[0].end_hour {
+            return Err(TariffError::Overlap { first: pair[0].name.clone(), second: pair[1].name.clone() });
+        }
+    }
+
+    if full_coverage {
+        let mut covered_until = 0;
+        for band in &sorted {
+            if band.start_hour > covered_until {
+                return Err(TariffError::Gap { from_hour: covered_until, to_hour: band.start_hour });
+            }
+            covered_until = band.end_hour;
+        }
+        if covered_until < 24 {
+            return Err(TariffError::Gap { from_hour: covered_until, to_hour: 24 });
+        }
+    }
+
+    Ok(())
+}
+
+/// Time-of-use tariff covering a full day.
+#[derive(Debug, Clone)]
+pub struct TariffSchedule {
+    bands: Vec<Tariff>,
+}
+
+impl Default for TariffSchedule {
+    /// Matches the bands seeded by the initial migration.
+    fn default() -> Self {
+        let band = |id, name: &str, rate, start_hour, end_hour| Tariff {
+            id,
+            name: name.to_string(),
+            rate,
+            start_hour,
+            end_hour,
+        };
+        Self {
+            bands: vec![
+                band(1, "Off-Peak", 0.10, 0, 6),
+                band(3, "Standard", 0.15, 6, 17),
+                band(2, "Peak", 0.30, 17, 21),
+                band(4, "Standard-Late", 0.15, 21, 24),
+            ],
+        }
+    }
+}
+
+impl TariffSchedule {
+    pub fn from_bands(mut bands: Vec<Tariff>) -> Result<Self, TariffError> {
+        validate(&bands, true)?;
+        bands.sort_by_key(|b| b.start_hour);
+        Ok(Self { bands })
+    }
+
+    /// Loads the time-of-use bands from the `tariffs` table.
+    pub async fn load(pool: &SqlitePool) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let bands = sqlx::query_as!(
+            Tariff,
+            "SELECT id, name, rate, start_hour, end_hour FROM tariffs"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Self::from_bands(bands)?)
+    }
+
+    fn band_at(&self, hour: f64) -> &Tariff {
+        let hour = hour.rem_euclid(24.0);
+        self.bands
+            .iter()
+            .find(|b| hour >= b.start_hour as f64 && hour < b.end_hour as f64)
+            .unwrap_or(&self.bands[self.bands.len() - 1])
+    }
+
+    /// Import price ($/kWh) at the given hour of day.
+    pub fn rate_at(&self, hour: f64) -> f64 {
+        self.band_at(hour).rate
+    }
+
+    /// Peak hours are the bands charged at the day's highest rate.
+    /// A flat tariff has no peak.
+    pub fn is_peak(&self, hour: f64) -> bool {
+        let max_rate = self.bands.iter().map(|b| b.rate).fold(f64::MIN, f64::max);
+        let min_rate = self.bands.iter().map(|b| b.rate).fold(f64::MAX, f64::min);
+        max_rate > min_rate && self.rate_at(hour) >= max_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band(name: &str, rate: f64, start_hour: i64, end_hour: i64) -> Tariff {
+        Tariff { id: 0, name: name.to_string(), rate, start_hour, end_hour }
+    }
+
+    #[test]
+    fn test_default_schedule_matches_seed() {
+        let schedule = TariffSchedule::default();
+        assert!(validate(&schedule.bands, true).is_ok());
+        assert_eq!(schedule.rate_at(3.0), 0.10);
+        assert_eq!(schedule.rate_at(16.5), 0.15);
+        assert!(!schedule.is_peak(16.5));
+        assert!(schedule.is_peak(17.0));
+        assert!(schedule.is_peak(20.5));
+        assert!(!schedule.is_peak(21.0));
+        assert_eq!(schedule.rate_at(23.5), 0.15);
+    }
+
+    #[test]
+    fn test_rejects_gaps_and_overlaps() {
+        let gap = vec![band("Night", 0.1, 0, 6), band("Day", 0.2, 7, 24)];
+        assert_eq!(validate(&gap, true), Err(TariffError::Gap { from_hour: 6, to_hour: 7 }));
+        assert!(validate(&gap, false).is_ok());
+
+        let overlap = vec![band("Night", 0.1, 0, 8), band("Day", 0.2, 6, 24)];
+        assert!(matches!(validate(&overlap, false), Err(TariffError::Overlap { .. })));
+
+        let negative = vec![band("Free", -0.1, 0, 24)];
+        assert!(matches!(validate(&negative, true), Err(TariffError::NegativeRate { .. })));
+
+        let inverted = vec![band("Odd", 0.1, 20, 4)];
+        assert!(matches!(validate(&inverted, false), Err(TariffError::InvalidRange { .. })));
+    }
+}
@@ -0,0 +1,218 @@
+use chrono::{NaiveDateTime, Timelike};
+use serde::{Deserialize, Serialize};
+use crate::models::{DeviceReading, EnergyData};
+use crate::clock::{DEFAULT_STEP_MINUTES, MAX_STEP_MINUTES};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    #[default]
+    Raw,
+    #[serde(rename = "30min")]
+    HalfHour,
+    Hour,
+    Day,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    /// Mean power (kW) over the bucket
+    #[default]
+    Avg,
+    /// Energy (kWh) over the bucket
+    Sum,
+}
+
+/// Telemetry aggregated over one time bucket.
+#[derive(Debug, Serialize, Clone)]
+pub struct EnergyBucket {
+    pub start: NaiveDateTime,
+    pub samples: usize,
+    pub grid_import: f64, // kW (avg) or kWh (sum)
+    pub grid_export: f64,
+    pub solar_generation: f64,
+    pub battery_charge: f64,
+    pub battery_discharge: f64,
+    pub home_consumption: f64,
+    pub battery_soc: f64, // SOC % at the end of the bucket
+    pub cost: f64, // $ total
+}
+
+impl Bucket {
+    fn start_of(self, ts: NaiveDateTime) -> NaiveDateTime {
+        let date = ts.date();
+        let start = match self {
+            Bucket::Raw => return ts,
+            Bucket::HalfHour => date.and_hms_opt(ts.hour(), ts.minute() / 30 * 30, 0),
+            Bucket::Hour => date.and_hms_opt(ts.hour(), 0, 0),
+            Bucket::Day => date.and_hms_opt(0, 0, 0),
+        };
+        start.unwrap_or(ts)
+    }
+}
+
+/// Hours each time-ordered sample covers: the shorter of the gaps to its neighbours, so a clock
+/// jump on one side does not count. Gaps that are not a possible simulator step are ignored; a
+/// sample without any neighbour covers one default step.
+fn sample_hours(rows: &[EnergyData]) -> Vec<f64> {
+    let gap = |a: &EnergyData, b: &EnergyData| {
+        let minutes = (b.timestamp - a.timestamp).num_seconds() as f64 / 60.0;
+        (minutes > 0.0 && minutes <= MAX_STEP_MINUTES as f64).then_some(minutes)
+    };
+    (0..rows.len())
+        .map(|i| {
+            let before = i.checked_sub(1).and_then(|j| gap(&rows[j], &rows[i]));
+            let after = rows.get(i + 1).and_then(|next| gap(&rows[i], next));
+            let minutes = match (before, after) {
+                (Some(before), Some(after)) => before.min(after),
+                (before, after) => before.or(after).unwrap_or(DEFAULT_STEP_MINUTES as f64),
+            };
+            minutes / 60.0
+        })
+        .collect()
+}
+
+/// Groups time-ordered samples into buckets. Sums weight each sample by the time it covers.
+pub fn aggregate(rows: &[EnergyData], bucket: Bucket, aggregation: Aggregation) -> Vec<EnergyBucket> {
+    let hours = sample_hours(rows);
+    let mut buckets: Vec<EnergyBucket> = Vec::new();
+
+    for (row, hours) in rows.iter().zip(hours) {
+        let start = bucket.start_of(row.timestamp);
+        if buckets.last().map(|b| b.start) != Some(start) {
+            buckets.push(EnergyBucket {
+                start,
+                samples: 0,
+                grid_import: 0.0,
+                grid_export: 0.0,
+                solar_generation: 0.0,
+                battery_charge: 0.0,
+                battery_discharge: 0.0,
+                home_consumption: 0.0,
+                battery_soc: 0.0,
+                cost: 0.0,
+            });
+        }
+
+        let weight = match aggregation {
+            Aggregation::Avg => 1.0,
+            Aggregation::Sum => hours,
+        };
+        let b = buckets.last_mut().expect("bucket was just pushed");
+        b.samples += 1;
+        b.grid_import += row.grid_import * weight;
+        b.grid_export += row.grid_export * weight;
+        b.solar_generation += row.solar_generation * weight;
+        b.battery_charge += row.battery_charge * weight;
+        b.battery_discharge += row.battery_discharge * weight;
+        b.home_consumption += row.home_consumption * weight;
+        b.battery_soc = row.battery_soc;
+        b.cost += row.cost;
+    }
+
+    for b in buckets.iter_mut().filter(|_| aggregation == Aggregation::Avg) {
+        let scale = 1.0 / b.samples as f64;
+        b.grid_import *= scale;
+        b.grid_export *= scale;
+        b.solar_generation *= scale;
+        b.battery_charge *= scale;
+        b.battery_discharge *= scale;
+        b.home_consumption *= scale;
+    }
+
+    buckets
+}
+
+/// One device's consumption aggregated over one time bucket.
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceBucket {
+    pub start: NaiveDateTime,
+    pub samples: usize,
+    pub power: f64, // kW mean
+    pub peak_power: f64, // kW
+    pub energy: f64, // kWh total
+}
+
+/// Groups one device's time-ordered readings into buckets.
+pub fn aggregate_device(rows: &[DeviceReading], bucket: Bucket) -> Vec<DeviceBucket> {
+    let mut buckets: Vec<DeviceBucket> = Vec::new();
+
+    for row in rows {
+        let start = bucket.start_of(row.timestamp);
+        if buckets.last().map(|b| b.start) != Some(start) {
+            buckets.push(DeviceBucket { start, samples: 0, power: 0.0, peak_power: 0.0, energy: 0.0 });
+        }
+
+        let b = buckets.last_mut().expect("bucket was just pushed");
+        b.samples += 1;
+        b.power += row.power;
+        b.peak_power = b.peak_power.max(row.power);
+        b.energy += row.energy;
+    }
+
+    for b in &mut buckets {
+        b.power /= b.samples as f64;
+    }
+
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn sample(hour: u32, minute: u32, import: f64) -> EnergyData {
+        EnergyData {
+            id: 0,
+            timestamp: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(hour, minute, 0).unwrap(),
+            grid_import: import,
+            grid_export: 0.0,
+            solar_generation: 0.0,
+            battery_charge: 0.0,
+            battery_discharge: 0.0,
+            home_consumption: import,
+            battery_soc: hour as f64,
+            cost: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_hourly_buckets() {
+        let rows = vec![sample(10, 0, 1.0), sample(10, 30, 3.0), sample(11, 0, 2.0)];
+
+        let avg = aggregate(&rows, Bucket::Hour, Aggregation::Avg);
+        assert_eq!(avg.len(), 2);
+        assert_eq!(avg[0].samples, 2);
+        assert!((avg[0].grid_import - 2.0).abs() < 1e-9);
+        assert!((avg[0].cost - 0.2).abs() < 1e-9);
+
+        let sum = aggregate(&rows, Bucket::Hour, Aggregation::Sum);
+        assert!((sum[0].grid_import - 2.0).abs() < 1e-9); // (1 + 3) kW * 0.5 h
+        assert!((sum[1].grid_import - 1.0).abs() < 1e-9);
+        assert_eq!(sum[1].battery_soc, 11.0);
+
+        // 10-minute steps, then a jump of three hours that does not count as energy
+        let rows = vec![sample(10, 0, 6.0), sample(10, 10, 6.0), sample(10, 20, 6.0), sample(13, 20, 6.0), sample(13, 30, 6.0)];
+        let sum = aggregate(&rows, Bucket::Day, Aggregation::Sum);
+        assert!((sum[0].grid_import - 5.0).abs() < 1e-9); // 6 kW * 5 samples * 1/6 h
+    }
+
+    #[test]
+    fn test_device_buckets() {
+        let reading = |minute: u32, power: f64| DeviceReading {
+            id: 0,
+            device_id: 1,
+            timestamp: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(10, minute, 0).unwrap(),
+            power,
+            energy: power * 0.5,
+        };
+        let buckets = aggregate_device(&[reading(0, 3.0), reading(30, 1.0)], Bucket::Hour);
+
+        assert_eq!(buckets.len(), 1);
+        assert!((buckets[0].power - 2.0).abs() < 1e-9);
+        assert_eq!(buckets[0].peak_power, 3.0);
+        assert!((buckets[0].energy - 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use chrono::NaiveDateTime;
+
+pub const HVAC: &str = "hvac";
+// °C the daily mean temperature drops from midsummer to midwinter
+const SEASONAL_SWING: f64 = 16.0;
+/// Longest pre-conditioning window; every step scans it for an upcoming peak
+pub const MAX_PRECONDITION_HOURS: f64 = 24.0;
+
+/// First-order (single thermal mass) model of the house.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Building {
+    pub thermal_capacity: f64, // kWh/°C stored in air, walls and furniture
+    pub heat_loss: f64, // kW/°C lost through the envelope
+    pub cop: f64, // Heat moved per unit of electricity, for heating and cooling alike
+}
+
+impl Default for Building {
+    fn default() -> Self {
+        Self { thermal_capacity: 3.0, heat_loss: 0.25, cop: 3.0 }
+    }
+}
+
+/// Daily outdoor temperature cycle, warmest at 15:00. Configured for midsummer; `seasonal`
+/// gives the weather on other days.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Weather {
+    pub mean_temp: f64, // °C
+    pub amplitude: f64, // °C above/below the mean at the warmest/coldest hour
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self { mean_temp: 27.0, amplitude: 7.0 }
+    }
+}
+
+impl Weather {
+    pub fn outdoor_temp(&self, hour: f64) -> f64 {
+        self.mean_temp + self.amplitude * ((hour - 15.0) * std::f64::consts::PI / 12.0).cos()
+    }
+
+    /// Weather on `day_of_year`, taking `self` as the weather at midsummer. The daily mean
+    /// follows a cosine through the year, coldest half a year after the warmest day.
+    pub fn seasonal(&self, day_of_year: u32, latitude_deg: f64) -> Weather {
+        let warmest_day = if latitude_deg >= 0.0 { 200.0 } else { 17.0 };
+        let phase = 2.0 * std::f64::consts::PI * (day_of_year as f64 - warmest_day) / 365.0;
+        Weather { mean_temp: self.mean_temp - SEASONAL_SWING * (1.0 - phase.cos()) / 2.0, ..self.clone() }
+    }
+}
+
+/// The temperatures the occupants accept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comfort {
+    pub setpoint: f64, // °C held outside of peak hours
+    pub min_temp: f64, // °C
+    pub max_temp: f64, // °C
+    /// How long before a peak window the house is pre-cooled/pre-heated (0 disables)
+    pub precondition_hours: f64,
+}
+
+impl Default for Comfort {
+    fn default() -> Self {
+        Self { setpoint: 22.0, min_temp: 20.0, max_temp: 25.0, precondition_hours: 2.0 }
+    }
+}
+
+impl Comfort {
+    pub fn validate(&self) -> Result<(), String> {
+        let values = [self.setpoint, self.min_temp, self.max_temp, self.precondition_hours];
+        if values.iter().any(|v| !v.is_finite()) {
+            return Err("Comfort settings must be finite numbers".to_string());
+        }
+        if self.setpoint < self.min_temp || self.setpoint > self.max_temp {
+            return Err("Comfort band must satisfy min_temp <= setpoint <= max_temp".to_string());
+        }
+        if !(0.0..=MAX_PRECONDITION_HOURS).contains(&self.precondition_hours) {
+            return Err(format!("precondition_hours must be between 0 and {}", MAX_PRECONDITION_HOURS));
+        }
+        Ok(())
+    }
+}
+
+/// What the thermostat aims for in a step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HvacMode {
+    /// Track the setpoint
+    Hold,
+    /// Store cooling/heating in the building ahead of a peak by moving to the far edge of the band
+    Precondition,
+    /// Run only as much as needed to stay inside the comfort band
+    Coast,
+}
+
+impl HvacMode {
+    /// Mode for a step given whether it is peak and whether a peak starts within the precondition window.
+    pub fn select(is_peak: bool, peak_ahead: bool) -> Self {
+        if is_peak {
+            HvacMode::Coast
+        } else if peak_ahead {
+            HvacMode::Precondition
+        } else {
+            HvacMode::Hold
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThermalState {
+    pub indoor_temp: f64, // °C
+    pub building: Building,
+    pub weather: Weather,
+    pub comfort: Comfort,
+}
+
+impl Default for ThermalState {
+    fn default() -> Self {
+        let comfort = Comfort::default();
+        Self {
+            indoor_temp: comfort.setpoint,
+            building: Building::default(),
+            weather: Weather::default(),
+            comfort,
+        }
+    }
+}
+
+impl ThermalState {
+    /// Thermostat mode for the step starting at `now`, pre-conditioning when a peak (as told by
+    /// `is_peak`) starts within the comfort settings' window.
+    pub fn mode(&self, now: NaiveDateTime, step_hours: f64, is_peak: impl Fn(NaiveDateTime) -> bool) -> HvacMode {
+        let lookahead = (self.comfort.precondition_hours / step_hours).ceil() as i64;
+        let step = chrono::Duration::minutes((step_hours * 60.0).round() as i64);
+        let peak_ahead = (1..=lookahead).any(|k| is_peak(now + step * k as i32));
+        HvacMode::select(is_peak(now), peak_ahead)
+    }
+
+    /// Fraction of the gap to the equilibrium temperature that remains after `hours`.
+    fn decay(&self, hours: f64) -> f64 {
+        (-hours * self.building.heat_loss / self.building.thermal_capacity).exp()
+    }
+
+    /// Indoor temperature after `hours` with a constant heat input `heat` (kW, negative = cooling).
+    pub fn temperature_after(&self, hour: f64, heat: f64, hours: f64) -> f64 {
+        let equilibrium = self.weather.outdoor_temp(hour) + heat / self.building.heat_loss;
+        equilibrium + (self.indoor_temp - equilibrium) * self.decay(hours)
+    }
+
+    /// Heat (kW, negative = cooling) the HVAC should deliver this step, limited by its electrical rating.
+    pub fn hvac_heat(&self, hour: f64, hours: f64, mode: HvacMode, rating: f64) -> f64 {
+        let outdoor = self.weather.outdoor_temp(hour);
+        let comfort = &self.comfort;
+        let (low, high) = match mode {
+            HvacMode::Hold => (comfort.setpoint, comfort.setpoint),
+            HvacMode::Precondition if outdoor >= comfort.setpoint => (comfort.min_temp, comfort.min_temp),
+            HvacMode::Precondition => (comfort.max_temp, comfort.max_temp),
+            HvacMode::Coast => (comfort.min_temp, comfort.max_temp),
+        };
+
+        let free = self.temperature_after(hour, 0.0, hours);
+        let target = free.clamp(low, high);
+        let decay = self.decay(hours);
+        if target == free || decay >= 1.0 {
+            return 0.0;
+        }
+
+        // Equilibrium temperature that lands exactly on the target, and the heat that sustains it
+        let equilibrium = (target - self.indoor_temp * decay) / (1.0 - decay);
+        let max_heat = rating * self.building.cop;
+        (self.building.heat_loss * (equilibrium - outdoor)).clamp(-max_heat, max_heat)
+    }
+
+    /// Electrical power (kW) drawn to deliver `heat`.
+    pub fn electric_power(&self, heat: f64) -> f64 {
+        heat.abs() / self.building.cop
+    }
+
+    /// The same house on `day_of_year`, with the configured midsummer weather moved to the season.
+    pub fn in_season(&self, day_of_year: u32, latitude_deg: f64) -> ThermalState {
+        ThermalState { weather: self.weather.seasonal(day_of_year, latitude_deg), ..self.clone() }
+    }
+
+    pub fn advance(&mut self, hour: f64, heat: f64, hours: f64) {
+        self.indoor_temp = self.temperature_after(hour, heat, hours);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holds_setpoint() {
+        let mut state = ThermalState { indoor_temp: 24.0, ..ThermalState::default() };
+        for step in 0..48 {
+            let hour = step as f64 / 2.0;
+            let heat = state.hvac_heat(hour, 0.5, HvacMode::Hold, 3.0);
+            state.advance(hour, heat, 0.5);
+            // Pulling down from 24 °C takes two steps at full power
+            if step >= 1 {
+                assert!((state.indoor_temp - state.comfort.setpoint).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_precooling_shifts_energy_out_of_peak() {
+        // Peak 17:00-21:00 with two hours of pre-cooling, compared to holding the setpoint all day
+        let run = |precondition: bool| {
+            let mut state = ThermalState::default();
+            let (mut peak_energy, mut total_energy) = (0.0, 0.0);
+            for step in 0..48 {
+                let hour = step as f64 / 2.0;
+                let is_peak = (17.0..21.0).contains(&hour);
+                let mode = if precondition {
+                    HvacMode::select(is_peak, (15.0..17.0).contains(&hour))
+                } else {
+                    HvacMode::Hold
+                };
+                let heat = state.hvac_heat(hour, 0.5, mode, 3.0);
+                let energy = state.electric_power(heat) * 0.5;
+                total_energy += energy;
+                if is_peak {
+                    peak_energy += energy;
+                }
+                state.advance(hour, heat, 0.5);
+                assert!(state.indoor_temp >= state.comfort.min_temp - 1e-9);
+                assert!(state.indoor_temp <= state.comfort.max_temp + 1e-9);
+            }
+            (peak_energy, total_energy)
+        };
+
+        let (peak_hold, _) = run(false);
+        let (peak_precool, total_precool) = run(true);
+        assert!(peak_precool < peak_hold * 0.5, "peak {} vs {}", peak_precool, peak_hold);
+        assert!(total_precool > 0.0);
+    }
+
+    #[test]
+    fn test_mode_follows_given_peak() {
+        let state = ThermalState::default();
+        let at = |hour: u32| chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(hour, 0, 0).unwrap();
+        // Peak 12:00-14:00, pre-conditioning two hours ahead
+        let is_peak = |t: NaiveDateTime| (at(12)..at(14)).contains(&t);
+        assert_eq!(state.mode(at(9), 0.5, is_peak), HvacMode::Hold);
+        assert_eq!(state.mode(at(10), 0.5, is_peak), HvacMode::Precondition);
+        assert_eq!(state.mode(at(12), 0.5, is_peak), HvacMode::Coast);
+        assert_eq!(state.mode(at(14), 0.5, is_peak), HvacMode::Hold);
+    }
+
+    #[test]
+    fn test_comfort_validation() {
+        assert!(Comfort::default().validate().is_ok());
+        assert!(Comfort { setpoint: f64::NAN, ..Comfort::default() }.validate().is_err());
+        assert!(Comfort { max_temp: f64::INFINITY, ..Comfort::default() }.validate().is_err());
+        assert!(Comfort { precondition_hours: -1.0, ..Comfort::default() }.validate().is_err());
+        assert!(Comfort { precondition_hours: 24.0, ..Comfort::default() }.validate().is_ok());
+        assert!(Comfort { precondition_hours: 1e9, ..Comfort::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_seasonal_weather() {
+        let summer = Weather::default();
+        assert!((summer.seasonal(200, 35.0).mean_temp - summer.mean_temp).abs() < 1e-9);
+        let winter = summer.seasonal(18, 35.0);
+        assert!((winter.mean_temp - (summer.mean_temp - SEASONAL_SWING)).abs() < 0.01);
+        assert_eq!(winter.amplitude, summer.amplitude);
+        // The seasons are reversed in the southern hemisphere
+        assert!(summer.seasonal(18, -35.0).mean_temp > summer.seasonal(200, -35.0).mean_temp);
+    }
+}
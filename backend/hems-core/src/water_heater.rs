@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+pub const WATER_HEATER: &str = "water_heater";
+
+// kWh needed to warm one litre of water by 1 °C
+const WATER_HEAT_CAPACITY: f64 = 4.186 / 3600.0;
+
+// Hot water drawn (litres at tank temperature) in each hour of the day: morning showers and evening use
+const DRAW_PROFILE: [f64; 24] = [
+    0.0, 0.0, 0.0, 0.0, 0.0, 5.0, 30.0, 35.0, 15.0, 5.0, 5.0, 5.0,
+    8.0, 5.0, 5.0, 5.0, 5.0, 8.0, 20.0, 25.0, 15.0, 10.0, 5.0, 0.0,
+];
+
+/// Hot water drawn (litres) over `hours` starting at `hour`.
+pub fn hot_water_draw(hour: f64, hours: f64) -> f64 {
+    DRAW_PROFILE[hour.rem_euclid(24.0) as usize] * hours
+}
+
+/// Storage tank of an electric water heater, modelled as a single well-mixed volume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WaterTank {
+    pub volume_litres: f64,
+    pub temperature: f64, // °C
+    pub setpoint: f64, // °C held by the thermostat
+    pub min_temp: f64, // °C, never allowed below this, even at peak
+    pub max_temp: f64, // °C, upper limit when storing solar surplus
+    pub inlet_temp: f64, // °C of the cold water replacing what is drawn
+    pub ambient_temp: f64, // °C around the tank
+    pub standby_loss: f64, // kW/°C lost through the insulation
+}
+
+impl Default for WaterTank {
+    fn default() -> Self {
+        Self {
+            volume_litres: 200.0,
+            temperature: 55.0,
+            setpoint: 55.0,
+            min_temp: 45.0,
+            max_temp: 75.0,
+            inlet_temp: 12.0,
+            ambient_temp: 20.0,
+            standby_loss: 0.002,
+        }
+    }
+}
+
+/// How the heating element is controlled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeatingStrategy {
+    /// Plain thermostat holding the setpoint
+    Thermostat,
+    /// Store solar surplus up to `max_temp` and only heat to `min_temp` during peak hours
+    Smart { is_peak: bool, solar_surplus: f64 },
+}
+
+impl WaterTank {
+    /// kWh per °C of stored water.
+    pub fn heat_capacity(&self) -> f64 {
+        self.volume_litres * WATER_HEAT_CAPACITY
+    }
+
+    /// Tank temperature after `hours` of heating at `power` kW while `draw` litres are used.
+    pub fn temperature_after(&self, power: f64, draw: f64, hours: f64) -> f64 {
+        // Drawn hot water is replaced by cold inlet water
+        let replaced = (draw / self.volume_litres).clamp(0.0, 1.0);
+        let mixed = self.temperature - replaced * (self.temperature - self.inlet_temp);
+        let loss = self.standby_loss * (mixed - self.ambient_temp);
+        mixed + (power - loss) * hours / self.heat_capacity()
+    }
+
+    /// Element power (kW) for the coming step, limited by the heater's `rating`.
+    pub fn heating_power(&self, hours: f64, rating: f64, draw: f64, strategy: HeatingStrategy) -> f64 {
+        let free = self.temperature_after(0.0, draw, hours);
+        let power_to = |target: f64| ((target - free) * self.heat_capacity() / hours).clamp(0.0, rating);
+
+        match strategy {
+            HeatingStrategy::Thermostat => power_to(self.setpoint),
+            HeatingStrategy::Smart { is_peak, solar_surplus } => {
+                let keep_warm = power_to(if is_peak { self.min_temp } else { self.setpoint });
+                let solar = power_to(self.max_temp).min(solar_surplus.max(0.0));
+                keep_warm.max(solar)
+            }
+        }
+    }
+
+    pub fn advance(&mut self, power: f64, draw: f64, hours: f64) {
+        self.temperature = self.temperature_after(power, draw, hours);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a day in half-hour steps and returns the heater energy (kWh) drawn during peak and in total
+    fn run_day(smart: bool, solar_surplus: impl Fn(f64) -> f64) -> (f64, f64, WaterTank) {
+        let mut tank = WaterTank::default();
+        let (mut peak, mut total) = (0.0, 0.0);
+        for step in 0..48 {
+            let hour = step as f64 / 2.0;
+            let is_peak = (17.0..21.0).contains(&hour);
+            let strategy = if smart {
+                HeatingStrategy::Smart { is_peak, solar_surplus: solar_surplus(hour) }
+            } else {
+                HeatingStrategy::Thermostat
+            };
+            let draw = hot_water_draw(hour, 0.5);
+            let power = tank.heating_power(0.5, 3.0, draw, strategy);
+            tank.advance(power, draw, 0.5);
+            assert!(tank.temperature >= tank.min_temp - 1e-9 && tank.temperature <= tank.max_temp + 1e-9);
+            total += power * 0.5;
+            if is_peak {
+                peak += power * 0.5;
+            }
+        }
+        (peak, total, tank)
+    }
+
+    #[test]
+    fn test_thermostat_holds_setpoint() {
+        let (_, total, tank) = run_day(false, |_| 0.0);
+        assert!((tank.temperature - tank.setpoint).abs() < 1e-9);
+        // Roughly the energy to heat the day's draw plus standby losses
+        let drawn: f64 = (0..24).map(|h| hot_water_draw(h as f64, 1.0)).sum();
+        let expected = drawn * WATER_HEAT_CAPACITY * (tank.setpoint - tank.inlet_temp);
+        assert!(total > expected && total < expected + 2.0, "{} vs {}", total, expected);
+    }
+
+    #[test]
+    fn test_smart_heating_stores_solar_and_avoids_peak() {
+        let solar = |hour: f64| if (10.0..15.0).contains(&hour) { 2.0 } else { 0.0 };
+        let (peak_thermostat, _, _) = run_day(false, solar);
+        let (peak_smart, _, _) = run_day(true, solar);
+        assert!(peak_thermostat > 0.0);
+        assert_eq!(peak_smart, 0.0);
+    }
+}
@@ -79,7 +79,7 @@ export default function Dashboard() {
             // Fetch load shifting status
             const lsRes = await fetch(`${apiUrl}/api/control/load-shifting`, { cache: 'no-store' });
             const lsJson = await lsRes.json();
-            setLoadShiftingEnabled(lsJson);
+            setLoadShiftingEnabled(lsJson.enabled);
         } catch (error) {
             console.error("Failed to fetch data", error);
         }
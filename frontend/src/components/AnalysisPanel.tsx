@@ -11,6 +11,9 @@ interface AnalysisRecord {
     home_consumption: number;
     grid_import: number;
     grid_export: number;
+    battery_charge: number;
+    battery_discharge: number;
+    battery_soc: number;
     cost: number;
     is_peak: boolean;
 }
@@ -54,7 +57,7 @@ export default function AnalysisPanel() {
     // Aggregate data for chart
     // We want to compare total cost or consumption per scenario
     
-    const scenarioOrder = ["Baseline", "Solar", "SmartShift"];
+    const scenarioOrder = ["Baseline", "Solar", "SmartShift", "SolarBattery", "SolarBatterySmartShift"];
     
     const rawChartData = data.reduce((acc, curr) => {
         if (!acc[curr.scenario]) {
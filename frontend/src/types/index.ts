@@ -8,6 +8,7 @@ export interface EnergyData {
     battery_discharge: number;
     home_consumption: number;
     battery_soc: number;
+    cost: number;
 }
 
 export interface Device {